@@ -0,0 +1,162 @@
+use std::collections::VecDeque;
+use std::sync::mpsc::{channel, Receiver, Sender};
+
+#[cfg(feature = "gui")]
+use ggez::graphics::{self, Color, DrawMode, DrawParam, PxScale, Rect, Text, TextFragment};
+#[cfg(feature = "gui")]
+use ggez::{Context, GameResult};
+
+use crate::bot::BotSkill;
+#[cfg(feature = "gui")]
+use crate::{SCREEN_HEIGHT, SCREEN_WIDTH};
+
+pub const ADMIN_HELP: &str = "Commands:
+    players              list the players with their ping
+    kick <name>          remove a player from the match
+    ban <name|ip>        remove a player and refuse its address from now on
+    map <file>           play on another map, the match starts over
+    restart              reset the scores and respawn everyone
+    limit <n|off>        end the match when a player reaches n
+    bots <n> [skill]     keep n bots in the match, skill is easy, normal or hard
+    help                 print this message";
+
+// output lines the host console keeps
+const CONSOLE_LINES: usize = 8;
+
+// Commands the dedicated servers stdin and the hosts console run on a live server
+#[derive(Debug, Clone)]
+pub enum AdminCommand {
+    Players,
+    Kick(String),       // player name
+    Ban(String),        // player name or ip address
+    ChangeMap(String),  // path of the map file
+    Restart,
+    ScoreLimit(Option<i32>),
+    Bots(usize, Option<BotSkill>), // how many bots and how good they are
+    Help,
+}
+
+// a command and where the server sends its answer, every command gets exactly one
+pub type AdminRequest = (AdminCommand, Sender<String>);
+
+impl AdminCommand {
+    pub fn parse(line: &str) -> Result<AdminCommand, String> {
+        let mut words = line.split_whitespace();
+        let command = words.next().unwrap_or("").to_lowercase();
+        let argument = words.collect::<Vec<&str>>().join(" ");
+        let needs_argument = |command: fn(String) -> AdminCommand| {
+            if argument.is_empty() {
+                Err("Missing argument, try help".to_string())
+            } else {
+                Ok(command(argument.clone()))
+            }
+        };
+        match command.as_str() {
+            "players" => Ok(AdminCommand::Players),
+            "kick" => needs_argument(AdminCommand::Kick),
+            "ban" => needs_argument(AdminCommand::Ban),
+            "map" => needs_argument(AdminCommand::ChangeMap),
+            "restart" => Ok(AdminCommand::Restart),
+            "limit" => match argument.as_str() {
+                "off" => Ok(AdminCommand::ScoreLimit(None)),
+                n => match n.parse::<i32>() {
+                    Ok(limit) if limit > 0 => Ok(AdminCommand::ScoreLimit(Some(limit))),
+                    _ => Err(format!("Invalid score limit: {}", n)),
+                },
+            },
+            "bots" => {
+                let mut words = argument.split_whitespace();
+                let count = words.next().unwrap_or("");
+                let count = count
+                    .parse::<usize>()
+                    .map_err(|_| format!("Invalid number of bots: {}", count))?;
+                let skill = match words.next() {
+                    Some(name) => {
+                        Some(BotSkill::preset(name).ok_or(format!("Unknown bot skill {}", name))?)
+                    }
+                    None => None,
+                };
+                Ok(AdminCommand::Bots(count, skill))
+            }
+            "help" => Ok(AdminCommand::Help),
+            _ => Err(format!("Unknown command {}, try help", command)),
+        }
+    }
+}
+
+// The hosts admin console in the game view, it talks to the server running in this process
+pub struct AdminConsole {
+    pub open: bool,
+    pub input: String,
+    lines: VecDeque<String>,
+    admin: Sender<AdminRequest>,
+    replies: (Sender<String>, Receiver<String>),
+}
+
+impl AdminConsole {
+    pub fn new(admin: Sender<AdminRequest>) -> AdminConsole {
+        AdminConsole {
+            open: false,
+            input: String::new(),
+            lines: VecDeque::new(),
+            admin,
+            replies: channel(),
+        }
+    }
+    // runs the typed command, the answer shows up with a later poll
+    pub fn submit(&mut self) {
+        let line = self.input.trim().to_string();
+        self.input.clear();
+        if line.is_empty() {
+            return;
+        }
+        self.push(format!("> {}", line));
+        match AdminCommand::parse(&line) {
+            Ok(command) => {
+                if self.admin.send((command, self.replies.0.clone())).is_err() {
+                    self.push("The server is not running".to_string());
+                }
+            }
+            Err(e) => self.push(e),
+        }
+    }
+    pub fn poll(&mut self) {
+        while let Ok(reply) = self.replies.1.try_recv() {
+            for line in reply.lines() {
+                self.push(line.to_string());
+            }
+        }
+    }
+    fn push(&mut self, line: String) {
+        if self.lines.len() == CONSOLE_LINES {
+            self.lines.pop_front();
+        }
+        self.lines.push_back(line);
+    }
+    #[cfg(feature = "gui")]
+    pub fn draw(&self, canvas: &mut graphics::Canvas, ctx: &mut Context) -> GameResult {
+        if !self.open {
+            return Ok(());
+        }
+        let line_height = 16.;
+        let height = line_height * (CONSOLE_LINES + 1) as f32 + 10.;
+        let rect = Rect::new(0., SCREEN_HEIGHT - height, SCREEN_WIDTH, height);
+        let background = graphics::Mesh::new_rectangle(ctx, DrawMode::fill(), rect, Color::WHITE)?;
+        let border = graphics::Mesh::new_rectangle(ctx, DrawMode::stroke(2.), rect, Color::BLACK)?;
+        canvas.draw(&background, DrawParam::default());
+        canvas.draw(&border, DrawParam::default());
+
+        let prompt = format!("admin> {}_", self.input);
+        for (i, line) in self.lines.iter().chain([&prompt]).enumerate() {
+            let text = Text::new(TextFragment {
+                text: line.clone(),
+                color: Some(Color::BLACK),
+                font: Some("LiberationMono-Regular".into()),
+                scale: Some(PxScale::from(14.)),
+            });
+            let y = rect.y + 5. + i as f32 * line_height;
+            canvas.draw(&text, DrawParam::default().dest([5., y]));
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,155 @@
+use std::env;
+use std::io;
+use std::net::{SocketAddr, UdpSocket};
+use std::process;
+use std::time::{Duration, Instant};
+
+use multiplayer_fps::reliability::Packet;
+use multiplayer_fps::server::{resolve_address, Message, ServerStatus};
+
+const USAGE: &str = "Usage: maze-wars-query <address> [options]
+
+Asks a running server what it is doing, without joining it.
+
+Options:
+    --json                print the status as JSON
+    --timeout <ms>        how long to wait for an answer (default 1000)
+    --help                print this message";
+
+// the request is repeated in case a datagram is lost
+const ATTEMPTS: u32 = 3;
+
+struct Args {
+    address: SocketAddr,
+    json: bool,
+    timeout: Duration,
+}
+
+fn main() {
+    let args = match parse_args(env::args().skip(1).collect()) {
+        Ok(args) => args,
+        Err(e) => {
+            eprintln!("{}\n\n{}", e, USAGE);
+            process::exit(2);
+        }
+    };
+    let status = match query(args.address, args.timeout) {
+        Ok(Some(status)) => status,
+        Ok(None) => {
+            eprintln!("No answer from {}", args.address);
+            process::exit(1);
+        }
+        Err(e) => {
+            eprintln!("Cant query {}: {}", args.address, e);
+            process::exit(1);
+        }
+    };
+    if args.json {
+        println!("{}", serde_json::to_string_pretty(&status).unwrap());
+    } else {
+        print_status(&status);
+    }
+}
+
+fn query(address: SocketAddr, timeout: Duration) -> io::Result<Option<ServerStatus>> {
+    let bind: SocketAddr = match address {
+        SocketAddr::V4(_) => "0.0.0.0:0".parse().unwrap(),
+        SocketAddr::V6(_) => "[::]:0".parse().unwrap(),
+    };
+    let socket = UdpSocket::bind(bind)?;
+    socket.set_read_timeout(Some(Duration::from_millis(50)))?;
+    let request = Packet::Unreliable(Message::StatusRequest).to_bytes();
+    let mut buf = [0; 2048];
+    for _ in 0..ATTEMPTS {
+        socket.send_to(&request, address)?;
+        let sent = Instant::now();
+        while sent.elapsed() < timeout / ATTEMPTS {
+            let (amt, src) = match socket.recv_from(&mut buf) {
+                Ok(received) => received,
+                Err(e)
+                    if matches!(
+                        e.kind(),
+                        io::ErrorKind::WouldBlock
+                            | io::ErrorKind::TimedOut
+                            | io::ErrorKind::ConnectionReset
+                            | io::ErrorKind::ConnectionRefused
+                    ) =>
+                {
+                    continue
+                }
+                Err(e) => return Err(e),
+            };
+            if src != address {
+                continue;
+            }
+            match Packet::from_bytes(&buf[..amt]) {
+                Ok(Packet::Unreliable(Message::StatusResponse(status))) => return Ok(Some(status)),
+                Ok(_) => {}
+                // most likely a server that speaks another protocol version
+                Err(e) => {
+                    return Err(io::Error::new(io::ErrorKind::InvalidData, e.to_string()))
+                }
+            }
+        }
+    }
+    Ok(None)
+}
+
+fn print_status(status: &ServerStatus) {
+    let uptime = status.uptime_secs;
+    println!("{} (protocol {})", status.name, status.protocol_version);
+    println!("Map:       {} ({}x{})", status.map, status.width, status.height);
+    println!(
+        "Players:   {}/{}, {} watching",
+        status.players.len(),
+        status.max_players,
+        status.spectators
+    );
+    println!("Password:  {}", if status.locked { "yes" } else { "no" });
+    println!(
+        "Uptime:    {}h {}m {}s",
+        uptime / 3600,
+        uptime / 60 % 60,
+        uptime % 60
+    );
+    for player in status.players.iter() {
+        let ping = match player.ping_ms {
+            Some(ping) => format!("{} ms", ping),
+            None => "-".to_string(),
+        };
+        println!("  {:16} {:5} {:>7}", player.name, player.score, ping);
+    }
+}
+
+fn parse_args(args: Vec<String>) -> Result<Args, String> {
+    let mut address = None;
+    let mut json = false;
+    let mut timeout = Duration::from_millis(1000);
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--help" => {
+                println!("{}", USAGE);
+                process::exit(0);
+            }
+            "--json" => json = true,
+            "--timeout" => {
+                let value = args.next().ok_or("Missing value for --timeout")?;
+                let ms = value
+                    .parse()
+                    .map_err(|_| format!("Invalid value for --timeout: {}", value))?;
+                timeout = Duration::from_millis(ms);
+            }
+            _ if arg.starts_with("--") => return Err(format!("Unknown option {}", arg)),
+            _ => {
+                let resolved = resolve_address(&arg).ok_or(format!("Invalid address {}", arg))?;
+                address = Some(resolved);
+            }
+        }
+    }
+    Ok(Args {
+        address: address.ok_or("Missing address")?,
+        json,
+        timeout,
+    })
+}
@@ -0,0 +1,190 @@
+use std::env;
+use std::io::{self, BufRead};
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::process;
+use std::sync::mpsc::{channel, Sender};
+use std::thread;
+use std::time::Duration;
+
+use multiplayer_fps::admin::{AdminCommand, AdminRequest};
+use multiplayer_fps::bot::BotSkill;
+use multiplayer_fps::maze::{is_playable, name_from_path, read_maze};
+use multiplayer_fps::password::password_key;
+use multiplayer_fps::server::{Server, ServerConfig, DEFAULT_PORT, MAX_PLAYERS};
+
+const USAGE: &str = "Usage: maze-wars-server --map <file> [options]
+
+Options:
+    --map <file>          map file to serve, e.g. maps/1.txt
+    --bind <ip>           address to listen on, e.g. 127.0.0.1 or :: (default 0.0.0.0)
+    --port <port>         port to listen on (default 35353)
+    --name <name>         server name shown to players
+    --max-players <n>     maximum number of players (default 8, at most 16)
+    --score-limit <n>     end the match when a player reaches this score
+    --ban <ip>            refuse players from this address, can be repeated
+    --password <text>     players need this password to join
+    --rate-limit <n>      packets per second one IP address may send (default 100)
+    --rate-burst <n>      packets an IP address may send at once (default 200)
+    --flood-ban <secs>    how long flooding addresses are banned (default 60)
+    --record <file>       write the match to a replay file
+    --bots <n>            fill free slots with up to n bots
+    --bot-skill <skill>   easy, normal or hard (default normal)
+    --bot-reaction <ms>   how long bots take to shoot a player they see
+    --bot-accuracy <n>    percentage of bot shots that hit
+    --help                print this message
+
+Type help while the server runs for the admin commands.";
+
+struct Args {
+    map: String,
+    bind: IpAddr,
+    port: u16,
+    config: ServerConfig,
+}
+
+// Runs the same server as the "Create game" view without opening a window
+fn main() {
+    let args = match parse_args(env::args().skip(1).collect()) {
+        Ok(args) => args,
+        Err(e) => {
+            eprintln!("{}\n\n{}", e, USAGE);
+            process::exit(2);
+        }
+    };
+    let maze = match read_maze(&args.map) {
+        Ok(maze) => maze,
+        Err(e) => {
+            eprintln!("Cant read map {}: {}", args.map, e);
+            process::exit(1);
+        }
+    };
+    if !is_playable(&maze) {
+        eprintln!("{} has no room to play", args.map);
+        process::exit(1);
+    }
+    let mut config = args.config;
+    config.map_name = name_from_path(&args.map);
+    let address = SocketAddr::new(args.bind, args.port);
+    let mut server = match Server::bind(address, config) {
+        Ok(server) => server,
+        Err(e) => {
+            eprintln!("Cant bind server to {}: {}", address, e);
+            process::exit(1);
+        }
+    };
+    let admin = server.admin_handle();
+    thread::spawn(move || read_commands(admin));
+    if let Err(e) = server.start(maze) {
+        eprintln!("Server stopped: {}", e);
+        process::exit(1);
+    }
+}
+
+// admin commands typed into the terminal, each answer is printed before the next is read
+fn read_commands(admin: Sender<AdminRequest>) {
+    for line in io::stdin().lock().lines() {
+        let line = match line {
+            Ok(line) => line,
+            Err(_) => return,
+        };
+        if line.trim().is_empty() {
+            continue;
+        }
+        let command = match AdminCommand::parse(&line) {
+            Ok(command) => command,
+            Err(e) => {
+                println!("{}", e);
+                continue;
+            }
+        };
+        let reply = channel();
+        if admin.send((command, reply.0)).is_err() {
+            return;
+        }
+        if let Ok(answer) = reply.1.recv() {
+            println!("{}", answer);
+        }
+    }
+}
+
+fn parse_args(args: Vec<String>) -> Result<Args, String> {
+    let mut map = None;
+    let mut bind = IpAddr::V4(Ipv4Addr::UNSPECIFIED);
+    let mut port = DEFAULT_PORT;
+    let mut config = ServerConfig {
+        dedicated: true,
+        ..ServerConfig::default()
+    };
+    // applied on top of --bot-skill, whatever the order
+    let mut reaction = None;
+    let mut accuracy = None;
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        if arg == "--help" {
+            println!("{}", USAGE);
+            process::exit(0);
+        }
+        let value = args
+            .next()
+            .ok_or_else(|| format!("Missing value for {}", arg))?;
+        match arg.as_str() {
+            "--map" => map = Some(value),
+            "--bind" => bind = parse_value(&arg, &value)?,
+            "--port" => port = parse_value(&arg, &value)?,
+            "--name" => config.name = value,
+            "--max-players" => match parse_value(&arg, &value)? {
+                n if n <= MAX_PLAYERS => config.max_players = n,
+                _ => return Err(format!("--max-players can be at most {}", MAX_PLAYERS)),
+            },
+            "--score-limit" => config.score_limit = Some(parse_value(&arg, &value)?),
+            "--password" => {
+                config.password = Some(value).filter(|p| !p.is_empty()).map(|p| password_key(&p))
+            }
+            "--ban" => {
+                config.banned.insert(parse_value(&arg, &value)?);
+            }
+            "--record" => config.record = Some(value),
+            "--bots" => config.bots = parse_value(&arg, &value)?,
+            "--bot-skill" => {
+                config.bot_skill = BotSkill::preset(&value)
+                    .ok_or_else(|| format!("Invalid value for --bot-skill: {}", value))?
+            }
+            "--bot-reaction" => reaction = Some(Duration::from_millis(parse_value(&arg, &value)?)),
+            "--bot-accuracy" => match parse_value::<u8>(&arg, &value)? {
+                percent if percent <= 100 => accuracy = Some(percent as f32 / 100.),
+                _ => return Err(format!("Invalid value for --bot-accuracy: {}", value)),
+            },
+            "--rate-limit" => config.rate_limit.packets_per_second = parse_positive(&arg, &value)?,
+            "--rate-burst" => config.rate_limit.burst = parse_positive(&arg, &value)?,
+            "--flood-ban" => {
+                config.rate_limit.ban_duration = Duration::from_secs(parse_value(&arg, &value)?)
+            }
+            _ => return Err(format!("Unknown option {}", arg)),
+        }
+    }
+    if let Some(reaction) = reaction {
+        config.bot_skill.reaction_time = reaction;
+    }
+    if let Some(accuracy) = accuracy {
+        config.bot_skill.accuracy = accuracy;
+    }
+    Ok(Args {
+        map: map.ok_or("Missing --map")?,
+        bind,
+        port,
+        config,
+    })
+}
+
+fn parse_positive(arg: &str, value: &str) -> Result<f32, String> {
+    match parse_value(arg, value)? {
+        n if n > 0. => Ok(n),
+        _ => Err(format!("{} must be greater than 0", arg)),
+    }
+}
+
+fn parse_value<T: std::str::FromStr>(arg: &str, value: &str) -> Result<T, String> {
+    value
+        .parse()
+        .map_err(|_| format!("Invalid value for {}: {}", arg, value))
+}
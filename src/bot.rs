@@ -0,0 +1,180 @@
+use std::collections::{HashMap, VecDeque};
+use std::time::{Duration, Instant};
+
+use glam::Vec2;
+
+use crate::maze::random_location;
+use crate::player::{first_in_line, Direction, Player, MOVE_INTERVAL};
+use crate::protocol::{quantise, PlayerId};
+
+// how good the bots of a server are
+#[derive(Debug, Clone, PartialEq)]
+pub struct BotSkill {
+    pub reaction_time: Duration, // from seeing a player to pulling the trigger
+    pub accuracy: f32,           // share of shots that hit, 0 to 1
+}
+
+impl Default for BotSkill {
+    fn default() -> Self {
+        BotSkill::preset("normal").unwrap()
+    }
+}
+
+impl BotSkill {
+    pub fn preset(name: &str) -> Option<BotSkill> {
+        let (reaction_ms, accuracy) = match name.to_lowercase().as_str() {
+            "easy" => (900, 0.4),
+            "normal" => (500, 0.7),
+            "hard" => (250, 0.95),
+            _ => return None,
+        };
+        Some(BotSkill {
+            reaction_time: Duration::from_millis(reaction_ms),
+            accuracy,
+        })
+    }
+}
+
+// what a bot does this tick, the server applies it like a message from a client
+#[derive(Debug, PartialEq)]
+pub enum BotAction {
+    Move((u16, u16), Direction),
+    Shoot(PlayerId),
+}
+
+// A player the server plays itself. It hunts the others through the corridors and
+// shoots them when they stand in its line of sight, see Player::first_in_line_of_sight.
+pub struct Bot {
+    pub skill: BotSkill,
+    path: VecDeque<(u16, u16)>, // cells still to walk, the next one first
+    aiming: Option<(PlayerId, Instant)>, // who the bot sees in front of it and since when
+    last_action: Instant,
+}
+
+impl Bot {
+    pub fn new(skill: BotSkill) -> Bot {
+        Bot {
+            skill,
+            path: VecDeque::new(),
+            aiming: None,
+            last_action: Instant::now(),
+        }
+    }
+    // after a respawn or a new map the old path leads nowhere
+    pub fn reset(&mut self) {
+        self.path.clear();
+        self.aiming = None;
+    }
+    // at most one move or shot per MOVE_INTERVAL, like a player holding a key
+    pub fn think(&mut self, me: &Player, others: &[&Player], maze: &[Vec<i32>]) -> Option<BotAction> {
+        if self.last_action.elapsed() < MOVE_INTERVAL {
+            return None;
+        }
+        let action = self.decide(me, others, maze);
+        if action.is_some() {
+            self.last_action = Instant::now();
+        }
+        action
+    }
+    fn decide(&mut self, me: &Player, others: &[&Player], maze: &[Vec<i32>]) -> Option<BotAction> {
+        // someone in front of it, shoot once the reaction time is over
+        if let Some(i) = me.first_in_line_of_sight(maze, others.iter().copied()) {
+            let target = others[i].id;
+            match self.aiming {
+                Some((aimed, since)) if aimed == target => {
+                    if since.elapsed() < self.skill.reaction_time {
+                        return None;
+                    }
+                    // the next shot needs another reaction time
+                    self.aiming = Some((target, Instant::now()));
+                    if rand::random::<f32>() < self.skill.accuracy {
+                        return Some(BotAction::Shoot(target));
+                    }
+                    return None;
+                }
+                _ => {
+                    self.aiming = Some((target, Instant::now()));
+                    return None;
+                }
+            }
+        }
+        self.aiming = None;
+        let cell = quantise(me.pos);
+        // someone in the corridor to the side or behind, turn towards it
+        for dir in [Direction::Up, Direction::Down, Direction::Left, Direction::Right] {
+            if dir != me.dir && first_in_line(me.pos, &dir, maze, others.iter().copied()).is_some() {
+                self.path.clear();
+                return Some(BotAction::Move(cell, dir));
+            }
+        }
+        if self.path.is_empty() {
+            self.path = self.next_path(cell, others, maze);
+        }
+        let next = *self.path.front()?;
+        if maze.get(next.1 as usize).and_then(|row| row.get(next.0 as usize)) != Some(&0) {
+            // the map changed under the bot
+            self.path.clear();
+            return None;
+        }
+        let dir = Direction::from_vec(&(
+            next.0 as f32 - cell.0 as f32,
+            next.1 as f32 - cell.1 as f32,
+        ));
+        if dir != me.dir {
+            return Some(BotAction::Move(cell, dir));
+        }
+        self.path.pop_front();
+        Some(BotAction::Move(next, dir))
+    }
+    // towards the closest player, anywhere when nobody is around
+    fn next_path(&self, from: (u16, u16), others: &[&Player], maze: &[Vec<i32>]) -> VecDeque<(u16, u16)> {
+        let hunt = others
+            .iter()
+            .filter_map(|other| find_path(maze, from, quantise(other.pos)))
+            .filter(|path| !path.is_empty())
+            .min_by_key(|path| path.len());
+        if let Some(path) = hunt {
+            return path;
+        }
+        random_location(maze)
+            .and_then(|(x, y)| find_path(maze, from, quantise(Vec2::new(x, y))))
+            .unwrap_or_default()
+    }
+}
+
+// shortest walk over free cells, without the start, None when the goal can not be reached
+pub fn find_path(maze: &[Vec<i32>], from: (u16, u16), to: (u16, u16)) -> Option<VecDeque<(u16, u16)>> {
+    let free = |cell: (u16, u16)| {
+        maze.get(cell.1 as usize).and_then(|row| row.get(cell.0 as usize)) == Some(&0)
+    };
+    if !free(to) {
+        return None;
+    }
+    let mut came_from: HashMap<(u16, u16), (u16, u16)> = HashMap::new();
+    let mut queue = VecDeque::from([from]);
+    while let Some(cell) = queue.pop_front() {
+        if cell == to {
+            let mut path = VecDeque::new();
+            let mut step = to;
+            while step != from {
+                path.push_front(step);
+                step = came_from[&step];
+            }
+            return Some(path);
+        }
+        let (x, y) = cell;
+        let neighbours = [
+            (x.wrapping_sub(1), y),
+            (x + 1, y),
+            (x, y.wrapping_sub(1)),
+            (x, y + 1),
+        ];
+        for next in neighbours {
+            if next != from && free(next) && !came_from.contains_key(&next) {
+                came_from.insert(next, cell);
+                queue.push_back(next);
+            }
+        }
+    }
+    None
+}
@@ -0,0 +1,109 @@
+use std::collections::VecDeque;
+
+use ggez::graphics::{self, Color, DrawParam, PxScale, Text, TextFragment};
+use ggez::{Context, GameResult};
+
+use crate::server::MAX_CHAT_LENGTH;
+use crate::SCREEN_HEIGHT;
+
+// lines kept for scrolling back
+const CHAT_HISTORY: usize = 50;
+const CHAT_FONT_SIZE: f32 = 14.;
+
+// The chat of a match with what happened in it, drawn under the score list
+#[derive(Default)]
+pub struct ChatLog {
+    lines: VecDeque<(String, Color)>,
+    pub input: Option<String>, // the line being typed, None while the input is closed
+    scroll: usize,             // newest lines hidden while scrolled back
+}
+
+impl ChatLog {
+    pub fn say(&mut self, name: &str, text: &str) {
+        self.push(format!("{}: {}", name, text), Color::BLACK);
+    }
+    // joins, leaves, kills and notices from the server
+    pub fn system(&mut self, text: String) {
+        self.push(text, Color::new(0.45, 0.45, 0.45, 1.0));
+    }
+    fn push(&mut self, line: String, color: Color) {
+        if self.lines.len() == CHAT_HISTORY {
+            self.lines.pop_front();
+        }
+        self.lines.push_back((line, color));
+        // stay on the lines the player scrolled back to
+        if self.scroll > 0 {
+            self.scroll = (self.scroll + 1).min(self.lines.len() - 1);
+        }
+    }
+    pub fn is_typing(&self) -> bool {
+        self.input.is_some()
+    }
+    pub fn open(&mut self) {
+        self.input = Some(String::new());
+    }
+    // the typed line if there is one to send, the input closes either way
+    pub fn close(&mut self) -> Option<String> {
+        self.scroll = 0;
+        self.input
+            .take()
+            .map(|text| text.trim().to_string())
+            .filter(|text| !text.is_empty())
+    }
+    pub fn add(&mut self, character: char) {
+        if let Some(input) = self.input.as_mut() {
+            if !character.is_control() && input.chars().count() < MAX_CHAT_LENGTH {
+                input.push(character);
+            }
+        }
+    }
+    pub fn remove_last(&mut self) {
+        if let Some(input) = self.input.as_mut() {
+            input.pop();
+        }
+    }
+    pub fn scroll_up(&mut self) {
+        self.scroll = (self.scroll + 1).min(self.lines.len().saturating_sub(1));
+    }
+    pub fn scroll_down(&mut self) {
+        self.scroll = self.scroll.saturating_sub(1);
+    }
+    // newest lines at the bottom, as many as fit between top and the bottom of the screen
+    pub fn draw(
+        &self,
+        canvas: &mut graphics::Canvas,
+        ctx: &mut Context,
+        x: f32,
+        top: f32,
+        width: f32,
+    ) -> GameResult {
+        let mut bottom = SCREEN_HEIGHT - 5.;
+        if let Some(input) = &self.input {
+            let text = ChatLog::text(format!("say: {}_", input), Color::BLACK, width);
+            bottom -= text.measure(ctx)?.y;
+            canvas.draw(&text, DrawParam::default().dest([x, bottom]));
+            bottom -= 5.;
+        }
+        for (line, color) in self.lines.iter().rev().skip(self.scroll) {
+            let text = ChatLog::text(line.clone(), *color, width);
+            let height = text.measure(ctx)?.y;
+            if bottom - height < top {
+                break;
+            }
+            bottom -= height;
+            canvas.draw(&text, DrawParam::default().dest([x, bottom]));
+        }
+        Ok(())
+    }
+    fn text(line: String, color: Color, width: f32) -> Text {
+        let mut text = Text::new(TextFragment {
+            text: line,
+            color: Some(color),
+            font: Some("LiberationMono-Regular".into()),
+            scale: Some(PxScale::from(CHAT_FONT_SIZE)),
+        });
+        text.set_bounds([width, f32::INFINITY]);
+        text.set_wrap(true);
+        text
+    }
+}
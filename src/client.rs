@@ -1,68 +1,382 @@
 use std::{
-    net::{SocketAddr, UdpSocket},
-    str::FromStr,
+    io,
+    net::{IpAddr, SocketAddr, UdpSocket},
     sync::{
-        mpsc::{Sender},
+        atomic::{AtomicBool, Ordering},
+        mpsc::Sender,
+        Mutex,
     },
+    time::{Duration, Instant},
 };
 
 use local_ip_address::local_ip;
 
-use crate::server::Message;
+use crate::map_transfer::MapAssembly;
+use crate::password::{password_key, password_proof};
+use crate::protocol::PROTOCOL_VERSION;
+use crate::reliability::{Packet, Reliability};
+use crate::replay::Recorder;
+use crate::server::{Message, FAREWELL_REPEATS, SESSION_GRACE_PERIOD};
+use crate::snapshot::{Snapshot, SnapshotHistory, World};
+use crate::transport::Transport;
+
+// Join is repeated until the server answers, lost joins and rejections are covered by that
+pub const JOIN_RETRY: Duration = Duration::from_millis(500);
+pub const JOIN_ATTEMPTS: u32 = 10;
+// the server answers every ping, this long without a word means it is lost
+pub const SERVER_TIMEOUT: Duration = Duration::from_secs(3);
 
 pub struct Client {
-    pub socket: UdpSocket,
+    pub socket: Box<dyn Transport>, // a UdpSocket unless the client runs on a MemoryNetwork
     pub name: String,
-    pub server_ip: String,
+    pub password: String, // empty when the server has none, it is never sent
+    pub spectator: bool,  // joins to watch the match without a player
+    server_ip: Mutex<SocketAddr>, // changes when another player takes over hosting
+    reliability: Mutex<Reliability>,
+    running: AtomicBool, // false once the player left, the threads of this client stop
+    relocated: AtomicBool, // the server moved, the session is resumed there
+    recorder: Mutex<Option<Recorder>>, // set while the player records the match
+}
+
+// What the listener of a client keeps between two polls
+pub struct Connection {
+    session: Option<u64>, // token from the servers Welcome
+    proof: u64,           // answer to the password challenge
+    resuming: bool,
+    join_attempts: u32,
+    last_request: Option<Instant>, // last Join or Resume
+    last_heard: Instant,
+    broken: bool, // the server stopped acking our reliable messages
+    map_assembly: MapAssembly,
+    snapshots: SnapshotHistory,
+    closed: bool, // rejected or the server closed, nothing more comes
+}
+
+impl Connection {
+    pub fn new() -> Connection {
+        Connection::default()
+    }
+}
+
+impl Default for Connection {
+    fn default() -> Connection {
+        Connection {
+            session: None,
+            proof: 0,
+            resuming: false,
+            join_attempts: 0,
+            last_request: None,
+            last_heard: Instant::now(),
+            broken: false,
+            map_assembly: MapAssembly::default(),
+            snapshots: SnapshotHistory::default(),
+            closed: false,
+        }
+    }
 }
 
 impl Client {
-    pub fn new(name: String, server_ip: String) -> Client {
-        let my_local_ip = local_ip().unwrap();
+    pub fn new(
+        name: String,
+        password: String,
+        spectator: bool,
+        server_address: SocketAddr,
+    ) -> io::Result<Client> {
+        let socket = UdpSocket::bind(Client::bind_address(&server_address))?;
+        Ok(Client::with_transport(name, password, spectator, server_address, Box::new(socket)))
+    }
+    pub fn with_transport(
+        name: String,
+        password: String,
+        spectator: bool,
+        server_address: SocketAddr,
+        socket: Box<dyn Transport>,
+    ) -> Client {
+        // wake up regularly to resend lost messages
+        socket
+            .set_read_timeout(Some(Duration::from_millis(50)))
+            .unwrap();
         Client {
-            socket: UdpSocket::bind(my_local_ip.to_string() + ":0").unwrap(),
+            socket,
             name,
-            server_ip,
+            password,
+            spectator,
+            server_ip: Mutex::new(server_address),
+            reliability: Mutex::new(Reliability::new()),
+            running: AtomicBool::new(true),
+            relocated: AtomicBool::new(false),
+            recorder: Mutex::new(None),
         }
     }
+    // local address in the same family and scope as the server
+    fn bind_address(server_address: &SocketAddr) -> SocketAddr {
+        let ip = if server_address.ip().is_loopback() {
+            server_address.ip()
+        } else if server_address.is_ipv6() {
+            IpAddr::from([0u16; 8])
+        } else {
+            local_ip().unwrap_or(IpAddr::from([0, 0, 0, 0]))
+        };
+        SocketAddr::new(ip, 0)
+    }
     pub fn listen_for_messages(&self, send_ch: Sender<Message>) {
-        let message = Message::ClientJoined((
-            self.name.clone(),
-            self.socket.local_addr().unwrap().to_string(),
-        ));
-        let message_bytes = serde_json::to_vec(&message).unwrap();
-        self.socket
-            .send_to(
-                &message_bytes,
-                SocketAddr::from_str(&self.server_ip).unwrap(),
-            )
-            .expect("Error on send");
-
+        let mut connection = Connection::new();
+        while let Some(messages) = self.poll(&mut connection) {
+            for m in messages {
+                if send_ch.send(m).is_err() {
+                    return;
+                }
+            }
+        }
+    }
+    // one step of the listener: joins or resumes when due, resends what was lost and
+    // handles at most one datagram, None once there is nothing more to listen for
+    pub fn poll(&self, connection: &mut Connection) -> Option<Vec<Message>> {
+        if !self.is_running() || connection.closed {
+            return None;
+        }
+        if self.relocated.swap(false, Ordering::Relaxed) {
+            connection.resuming = true;
+            connection.last_request = None;
+        }
+        let request_due = connection
+            .last_request
+            .is_none_or(|sent| sent.elapsed() >= JOIN_RETRY);
+        match connection.session {
+            None if request_due => {
+                if connection.join_attempts == JOIN_ATTEMPTS {
+                    println!("CLIENT: No answer from {}", self.server_address());
+                    return None;
+                }
+                self.send(&self.join_message(connection.proof));
+                connection.join_attempts += 1;
+                connection.last_request = Some(Instant::now());
+            }
+            // the server went quiet, pick the session up again without bothering the game
+            Some(token)
+                if connection.resuming
+                    || connection.broken
+                    || connection.last_heard.elapsed() >= SERVER_TIMEOUT =>
+            {
+                if connection.last_heard.elapsed() >= SESSION_GRACE_PERIOD {
+                    println!("CLIENT: Could not resume the session");
+                    return None;
+                }
+                if !connection.resuming {
+                    let server_ip = self.server_address();
+                    println!("CLIENT: Lost {}, resuming", server_ip);
+                    // the server starts over with fresh sequence numbers as well
+                    self.reliability.lock().unwrap().forget(&server_ip);
+                    connection.resuming = true;
+                    connection.broken = false;
+                }
+                if request_due {
+                    self.send(&Message::Resume(PROTOCOL_VERSION, token));
+                    connection.last_request = Some(Instant::now());
+                }
+            }
+            _ => {}
+        }
+        connection.broken |= self.resend_lost() && connection.session.is_some();
         let mut buf = [0; 2048];
-        loop {
-            match self.socket.recv_from(&mut buf) {
-                Ok((amt, _)) => {
-                    let m: Message =
-                        serde_json::from_slice(&buf[..amt]).expect("Cant serialize from slice.");
+        let (amt, src) = match self.socket.recv_from(&mut buf) {
+            Ok(received) => received,
+            // a closed server port is reported on some systems, it may come back
+            Err(e)
+                if matches!(
+                    e.kind(),
+                    io::ErrorKind::WouldBlock
+                        | io::ErrorKind::TimedOut
+                        | io::ErrorKind::ConnectionReset
+                        | io::ErrorKind::ConnectionRefused
+                ) =>
+            {
+                return Some(Vec::new());
+            }
+            Err(_e) => {
+                return None;
+            }
+        };
+        // anyone can send us datagrams, only the server may move or hand over
+        // the match and only its datagrams show that it is alive
+        if src != self.server_address() {
+            return Some(Vec::new());
+        }
+        connection.last_heard = Instant::now();
+        let packet = match Packet::from_bytes(&buf[..amt]) {
+            Ok(packet) => packet,
+            Err(e) => {
+                println!("CLIENT: Bad datagram from {}: {}", src, e);
+                return Some(Vec::new());
+            }
+        };
+        let (messages, ack) = self.reliability.lock().unwrap().receive(src, packet);
+        if let Some(ack) = ack {
+            self.send_bytes(&ack);
+        }
 
-                    match &m {
-                        Message::ClientJoined((name, ip_address)) => {
-                            println!("CLIENT: New user joined: {} {}", name, ip_address);
-                        }
-                        _ => {}
-                    };
-                    send_ch.send(m).unwrap();
+        let mut received = Vec::new();
+        for m in messages {
+            let m = match m {
+                Message::MapChunk(chunk) => match connection.map_assembly.add(chunk) {
+                    Some(Ok(maze)) => Message::Map(maze),
+                    Some(Err(e)) => {
+                        println!("CLIENT: Broken map transfer {:?}, asking again", e);
+                        self.send(&Message::MapRequest);
+                        continue;
+                    }
+                    None => continue,
+                },
+                // answered right away, later Joins repeat the answer
+                Message::PasswordChallenge(nonce) => {
+                    connection.proof = password_proof(password_key(&self.password), nonce);
+                    self.send(&self.join_message(connection.proof));
+                    continue;
                 }
-
-                Err(_e) => {
-                    return;
+                // the game only sees full snapshots
+                Message::Snapshot(snapshot) => {
+                    match self.apply(&mut connection.snapshots, snapshot) {
+                        Some(full) => Message::Snapshot(full),
+                        None => continue,
+                    }
+                }
+                m => m,
+            };
+            match &m {
+                Message::ClientJoined((id, name)) => {
+                    println!("CLIENT: New user joined: {} ({})", name, id);
                 }
+                Message::Welcome((_, token)) => {
+                    connection.session = Some(*token);
+                    connection.resuming = false;
+                    // a new or resumed session counts its ticks from scratch
+                    connection.snapshots.clear();
+                }
+                Message::JoinRejected(reason) => {
+                    println!("CLIENT: Server rejected us: {}", reason);
+                }
+                Message::ServerMoved(address) => {
+                    println!("CLIENT: Server moved to {}", address);
+                    self.move_to(*address);
+                }
+                _ => {}
+            };
+            self.record(false, &m);
+            // nothing more to listen for after a rejection or when the server is gone
+            let last = matches!(m, Message::JoinRejected(_) | Message::ServerClosed);
+            received.push(m);
+            if last {
+                connection.closed = true;
+                break;
             }
         }
+        Some(received)
     }
 
+    // rebuilds the world from the baseline and acks it, None for snapshots that are
+    // older than the newest one or whose baseline is gone
+    fn apply(&self, snapshots: &mut SnapshotHistory, snapshot: Snapshot) -> Option<Snapshot> {
+        if snapshot.tick <= snapshots.latest_tick() {
+            return None;
+        }
+        let world = if snapshot.baseline == 0 {
+            snapshot.apply(&World::new())?
+        } else {
+            snapshot.apply(snapshots.get(snapshot.baseline)?)?
+        };
+        self.send(&Message::SnapshotAck(snapshot.tick));
+        let mut full = Snapshot::full(snapshot.tick, &world);
+        full.last_move = snapshot.last_move;
+        snapshots.push(snapshot.tick, world);
+        Some(full)
+    }
+    fn join_message(&self, proof: u64) -> Message {
+        Message::Join(PROTOCOL_VERSION, self.name.clone(), proof, self.spectator)
+    }
+    pub fn is_running(&self) -> bool {
+        self.running.load(Ordering::Relaxed)
+    }
+    // tells the server we are gone and stops listening
+    pub fn leave(&self) {
+        if self.running.swap(false, Ordering::Relaxed) {
+            for _ in 0..FAREWELL_REPEATS {
+                self.send(&Message::Leave);
+            }
+            self.stop_recording();
+        }
+    }
+    pub fn server_address(&self) -> SocketAddr {
+        *self.server_ip.lock().unwrap()
+    }
+    // continues the session on another server, the listener resumes it there
+    pub fn move_to(&self, address: SocketAddr) {
+        let mut server_ip = self.server_ip.lock().unwrap();
+        self.reliability.lock().unwrap().forget(&server_ip);
+        *server_ip = address;
+        self.relocated.store(true, Ordering::Relaxed);
+    }
+    // a server this client starts to take over the match listens on the address of the
+    // client, the others are sent where the old host saw the client and the replies
+    // come from the address the client sends to
+    pub fn host_ip(&self) -> io::Result<IpAddr> {
+        Ok(self.socket.local_addr()?.ip())
+    }
+    // the server this client started on `host_ip` took over the match, the old host
+    // learns the port and the session goes on there
+    pub fn take_over(&self, address: SocketAddr) {
+        for _ in 0..FAREWELL_REPEATS {
+            self.send(&Message::HostReady(address.port()));
+        }
+        self.move_to(address);
+    }
+    // records what the game gets from now on, `seed` is what it already knows
+    // written as if it was just received
+    pub fn start_recording(&self, mut recorder: Recorder, seed: Vec<Message>) {
+        let server_ip = self.server_address().to_string();
+        for m in seed {
+            recorder.record(false, &server_ip, &m);
+        }
+        *self.recorder.lock().unwrap() = Some(recorder);
+    }
+    pub fn stop_recording(&self) {
+        self.recorder.lock().unwrap().take();
+    }
+    pub fn is_recording(&self) -> bool {
+        self.recorder.lock().unwrap().is_some()
+    }
+    fn record(&self, sent: bool, msg: &Message) {
+        if let Some(recorder) = self.recorder.lock().unwrap().as_mut() {
+            recorder.record(sent, &self.server_address().to_string(), msg);
+        }
+    }
     pub fn send_ping_msg(&self) {
-        let m = serde_json::to_vec(&Message::Ping(self.name.clone())).unwrap();
-        self.socket.send_to(&m, &self.server_ip).unwrap();
+        self.send(&Message::Ping);
+    }
+    pub fn send(&self, msg: &Message) {
+        let server_ip = self.server_address();
+        let bytes = self
+            .reliability
+            .lock()
+            .unwrap()
+            .prepare(server_ip, msg.clone());
+        self.send_bytes(&bytes);
+        self.record(true, msg);
+    }
+    fn send_bytes(&self, bytes: &[u8]) {
+        if let Err(e) = self.socket.send_to(bytes, self.server_address()) {
+            println!("CLIENT: cant send message: {}", e);
+        }
+    }
+    // true when the server did not ack a message in time and the channel was dropped
+    fn resend_lost(&self) -> bool {
+        let server_ip = self.server_address();
+        let (resends, failed) = {
+            let mut reliability = self.reliability.lock().unwrap();
+            (reliability.resends(), reliability.failed_peers())
+        };
+        for (_, bytes) in resends {
+            self.send_bytes(&bytes);
+        }
+        failed.contains(&server_ip)
     }
 }
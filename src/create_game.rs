@@ -1,9 +1,10 @@
 use ggez::graphics::{Color, DrawParam, TextAlign, TextLayout};
-use local_ip_address::local_ip;
 use std::collections::HashMap;
 use std::fs;
-use std::net::UdpSocket;
 
+use crate::maze::{is_playable, name_from_path, read_maze};
+use crate::password::password_key;
+use crate::server::{resolve_address, Server, ServerConfig, DEFAULT_PORT};
 use crate::state::Map;
 use crate::{SCREEN_WIDTH};
 use crate::drawer::{Button, Drawer, Input};
@@ -18,13 +19,20 @@ use super::{game::GameStruct, main_menu::MainMenuStruct};
 pub struct CreateGameStruct {
     pub element_rects: HashMap<String, Rect>, // holds text input and button rects
     pub name_input_active: bool,
+    pub address_input_active: bool,
+    pub password_input_active: bool,
     pub name: Text,
+    pub address: Text, // interface and port the server binds to
+    pub password: Text, // players need it to join, empty for an open game
     pub drawer: Drawer,
-    pub display_error: bool,
+    pub error: Option<String>,
     pub map_name: Option<String>,
+    pub server: Option<Server>, // bound when the game is created
     maps: Vec<Text>,
 }
 
+const MAPS_Y: f32 = 500.0;
+
 impl CreateGameStruct {
     pub fn new(ctx: &mut Context) -> GameResult<CreateGameStruct> {
         let drawer = Drawer::new(ctx)?;
@@ -32,10 +40,15 @@ impl CreateGameStruct {
         Ok(CreateGameStruct {
             element_rects: Self::get_elements(&drawer.button_dimensions, &drawer.input_dimensions),
             name_input_active: false,
+            address_input_active: false,
+            password_input_active: false,
             name: Text::new(""),
-            display_error: false,
+            address: Text::new(format!("0.0.0.0:{}", DEFAULT_PORT)),
+            password: Text::new(""),
+            error: None,
             drawer,
             map_name: Some(maps[0].contents()),
+            server: None,
             maps,
         })
     }
@@ -62,7 +75,7 @@ impl CreateGameStruct {
             "CREATE_GAME".to_string(),
             graphics::Rect::new(
                 button_dimensions.horizontal_offset,
-                275.0,
+                425.0,
                 button_dimensions.width,
                 button_dimensions.height,
             ),
@@ -76,6 +89,24 @@ impl CreateGameStruct {
                 input_dimensions.height,
             ),
         );
+        elems.insert(
+            "ADDRESS_INPUT".to_string(),
+            graphics::Rect::new(
+                input_dimensions.horizontal_offset,
+                275.0,
+                input_dimensions.width,
+                input_dimensions.height,
+            ),
+        );
+        elems.insert(
+            "PASSWORD_INPUT".to_string(),
+            graphics::Rect::new(
+                input_dimensions.horizontal_offset,
+                350.0,
+                input_dimensions.width,
+                input_dimensions.height,
+            ),
+        );
         elems.insert(
             "BACK_ARROW_IMG".to_string(),
             graphics::Rect::new(100.0 - 6.0, 100.0 - 6.0, 256.0 * 0.15, 256.0 * 0.15),
@@ -84,8 +115,8 @@ impl CreateGameStruct {
         elems
     }
 
-    pub fn draw_error_message(&self, canvas: &mut graphics::Canvas) {
-        let mut text = Text::new("Server already running on this network");
+    pub fn draw_error_message(&self, canvas: &mut graphics::Canvas, error: &str) {
+        let mut text = Text::new(error);
         let create_game_btn_rect = self.element_rects.get("CREATE_GAME").unwrap();
         let text_x = create_game_btn_rect.x + create_game_btn_rect.w / 2.0;
         let text_y = create_game_btn_rect.y + create_game_btn_rect.h + 10.0;
@@ -98,8 +129,8 @@ impl CreateGameStruct {
     }
 
     pub fn draw(&self, canvas: &mut graphics::Canvas, ctx: &mut Context) -> GameResult {
-        if self.display_error {
-            self.draw_error_message(canvas)
+        if let Some(error) = &self.error {
+            self.draw_error_message(canvas, error)
         }
         self.drawer.draw_title(canvas, ctx)?;
         self.drawer.draw_name_input(
@@ -111,6 +142,24 @@ impl CreateGameStruct {
             *self.element_rects.get("NAME_INPUT").unwrap(),
         )?;
 
+        self.drawer.draw_ip_input(
+            canvas,
+            ctx,
+            275.0,
+            self.address_input_active,
+            self.address.contents(),
+            *self.element_rects.get("ADDRESS_INPUT").unwrap(),
+        )?;
+
+        self.drawer.draw_password_input(
+            canvas,
+            ctx,
+            350.0,
+            self.password_input_active,
+            self.password.contents(),
+            *self.element_rects.get("PASSWORD_INPUT").unwrap(),
+        )?;
+
         self.drawer.draw_back_arrow_img(
             canvas,
             ctx,
@@ -120,14 +169,14 @@ impl CreateGameStruct {
         self.drawer.draw_create_game_button(
             canvas,
             ctx,
-            275.0,
+            425.0,
             *self.element_rects.get("CREATE_GAME").unwrap(),
         )?;
         self.drawer
             .draw_fps_counter(canvas, ctx)
             .expect("Cant draw fps counter.");
         let start_x = SCREEN_WIDTH /2.0;
-        let start_y = 350.0;
+        let start_y = MAPS_Y;
         for (i,map) in self.maps.iter().enumerate(){
             if self.map_name.is_none(){
                 canvas.draw(map, DrawParam::from([start_x, start_y + i as f32 * 20.]).color(Color::BLACK))
@@ -155,7 +204,7 @@ impl CreateGameStruct {
             let location = map.measure(ctx).unwrap();
             let width = location.x *2.0;
             let x = SCREEN_WIDTH/2. - location.x;
-            let y = 350. + 16. * (map_index -1.);
+            let y = MAPS_Y + 16. * (map_index -1.);
             let height = 16.;
             if mouse_x > x && mouse_x < x + width && mouse_y > y && mouse_y < y +height{
                 self.map_name = Some(map.contents());
@@ -168,15 +217,57 @@ impl CreateGameStruct {
             {
                 if name == "NAME_INPUT" {
                     self.name_input_active = true;
+                    self.address_input_active = false;
+                    self.password_input_active = false;
+                } else if name == "ADDRESS_INPUT" {
+                    self.address_input_active = true;
+                    self.name_input_active = false;
+                    self.password_input_active = false;
+                } else if name == "PASSWORD_INPUT" {
+                    self.password_input_active = true;
+                    self.name_input_active = false;
+                    self.address_input_active = false;
                 } else if name == "CREATE_GAME" {
-                    match UdpSocket::bind(local_ip().unwrap().to_string() + ":35353") {
+                    if self.name.contents().is_empty() {
+                        self.error = Some("Enter your name first".to_string());
+                        break;
+                    }
+                    let address = match resolve_address(&self.address.contents()) {
+                        Some(address) => address,
+                        None => {
+                            self.error = Some("Invalid address".to_string());
+                            break;
+                        }
+                    };
+                    let map_path = self.map_name.clone().unwrap();
+                    let maze = match read_maze(&map_path) {
+                        Ok(maze) if is_playable(&maze) => maze,
                         Ok(_) => {
+                            self.error = Some("This map has no room to play".to_string());
+                            break;
+                        }
+                        Err(e) => {
+                            self.error = Some(format!("Cant read map: {}", e));
+                            break;
+                        }
+                    };
+                    let config = ServerConfig {
+                        name: format!("{}'s game", self.name.contents()),
+                        map_name: name_from_path(&map_path),
+                        password: Some(self.password.contents())
+                            .filter(|p| !p.is_empty())
+                            .map(|p| password_key(&p)),
+                        ..ServerConfig::default()
+                    };
+                    match Server::bind(address, config) {
+                        Ok(server) => {
+                            self.server = Some(server);
                             let player_name = self.name.contents();
-                            let map = Map::make_from_file(ctx, self.map_name.as_ref().unwrap());
+                            let map = Map::new(ctx, maze);
                             new_view = Some(View::Game(GameStruct::new(ctx, player_name, map,(0.,0.)).unwrap()));
                         }
                         Err(_) => {
-                            self.display_error = true;
+                            self.error = Some(format!("Cant host on {}, port in use?", address));
                         }
                     }
 
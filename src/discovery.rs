@@ -0,0 +1,74 @@
+use std::{
+    io,
+    net::{IpAddr, SocketAddr, UdpSocket},
+    sync::mpsc::Sender,
+    time::{Duration, Instant},
+};
+
+use local_ip_address::local_ip;
+
+use crate::reliability::Packet;
+use crate::server::{Message, ServerInfo, DEFAULT_PORT};
+
+// servers are searched on DEFAULT_PORT and the ports right after it
+pub const DISCOVERY_PORTS: u16 = 10;
+
+// Asks every server on the LAN to describe itself and sends each answer to `found`.
+// Servers bound to 0.0.0.0 answer the broadcast, servers bound to loopback or to
+// this machines LAN address are asked directly.
+pub fn search(found: Sender<(SocketAddr, ServerInfo)>, wait: Duration) -> io::Result<()> {
+    let socket = UdpSocket::bind("0.0.0.0:0")?;
+    socket.set_broadcast(true)?;
+    socket.set_read_timeout(Some(Duration::from_millis(100)))?;
+
+    let mut targets = vec![
+        IpAddr::from([255, 255, 255, 255]),
+        IpAddr::from([127, 0, 0, 1]),
+    ];
+    if let Ok(ip) = local_ip() {
+        targets.push(ip);
+    }
+    let request = Packet::Unreliable(Message::DiscoveryRequest).to_bytes();
+    for port in DEFAULT_PORT..DEFAULT_PORT + DISCOVERY_PORTS {
+        for ip in targets.iter() {
+            if let Err(e) = socket.send_to(&request, SocketAddr::new(*ip, port)) {
+                println!("Cant send discovery request to {}:{}: {}", ip, port, e);
+            }
+        }
+    }
+
+    let started = Instant::now();
+    let mut buf = [0; 2048];
+    while started.elapsed() < wait {
+        let (amt, src) = match socket.recv_from(&mut buf) {
+            Ok(received) => received,
+            // windows reports closed ports of the unicast targets as resets
+            Err(e)
+                if matches!(
+                    e.kind(),
+                    io::ErrorKind::WouldBlock
+                        | io::ErrorKind::TimedOut
+                        | io::ErrorKind::ConnectionReset
+                        | io::ErrorKind::ConnectionRefused
+                ) =>
+            {
+                continue
+            }
+            Err(e) => return Err(e),
+        };
+        if let Ok(Packet::Unreliable(Message::DiscoveryResponse(info))) =
+            Packet::from_bytes(&buf[..amt])
+        {
+            if found.send((src, info)).is_err() {
+                return Ok(());
+            }
+        }
+    }
+    Ok(())
+}
+
+// true when the address points to this machine, a local server answers
+// both on loopback and on the LAN address
+pub fn is_this_machine(ip: &IpAddr) -> bool {
+    ip.is_loopback() || local_ip().map(|local| local == *ip).unwrap_or(false)
+}
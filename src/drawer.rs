@@ -242,6 +242,41 @@ impl Drawer {
         Ok(())
     }
 
+    pub fn draw_button(
+        &self,
+        canvas: &mut graphics::Canvas,
+        ctx: &mut Context,
+        y: f32,
+        button_rect: Rect,
+        label: &str,
+    ) -> GameResult {
+        // button rect
+        let button = graphics::Mesh::new_rectangle(
+            ctx,
+            DrawMode::stroke(1.0),
+            button_rect,
+            Color::BLACK,
+        )?;
+        canvas.draw(&button, DrawParam::default());
+
+        // button text
+        let mut button_text = Text::new(label);
+        button_text.set_layout(TextLayout {
+            v_align: TextAlign::Middle,
+            h_align: TextAlign::Middle,
+        });
+        canvas.draw(
+            &button_text,
+            DrawParam::from(Vec2::new(
+                self.button_dimensions.horizontal_offset + self.button_dimensions.width / 2.0,
+                y + self.button_dimensions.height / 2.0,
+            ))
+            .color(Color::BLACK),
+        );
+
+        Ok(())
+    }
+
     pub fn draw_back_arrow_img(
         &self,
         canvas: &mut graphics::Canvas,
@@ -312,6 +347,60 @@ impl Drawer {
         Ok(())
     }
 
+    // the password is shown as stars
+    pub fn draw_password_input(
+        &self,
+        canvas: &mut graphics::Canvas,
+        ctx: &mut Context,
+        y: f32,
+        password_input_active: bool,
+        password: String,
+        password_input_rect: Rect,
+    ) -> GameResult {
+        let password_input_draw_mode = if password_input_active {
+            DrawMode::stroke(3.0)
+        } else {
+            DrawMode::stroke(1.0)
+        };
+        // input box
+        let password_input = graphics::Mesh::new_rectangle(
+            ctx,
+            password_input_draw_mode,
+            password_input_rect,
+            Color::BLACK,
+        )?;
+        canvas.draw(&password_input, DrawParam::default());
+        // label
+        let mut password_input_label = Text::new("PASSWORD (OPTIONAL)");
+        password_input_label.set_layout(TextLayout {
+            v_align: TextAlign::End,
+            h_align: TextAlign::Middle,
+        });
+        canvas.draw(
+            &password_input_label,
+            DrawParam::from(Vec2::new(
+                self.input_dimensions.horizontal_offset + self.input_dimensions.width / 2.0,
+                y - 5.0,
+            ))
+            .color(Color::BLACK),
+        );
+        //input box text
+        let mut password_input = Text::new("*".repeat(password.chars().count()));
+        password_input.set_layout(TextLayout {
+            v_align: TextAlign::Middle,
+            h_align: TextAlign::Begin,
+        });
+        canvas.draw(
+            &password_input,
+            DrawParam::from(Vec2::new(
+                self.input_dimensions.horizontal_offset + self.input_dimensions.padding,
+                y + (self.input_dimensions.height / 2.0),
+            ))
+            .color(Color::BLACK),
+        );
+        Ok(())
+    }
+
     pub fn draw_title(&self, canvas: &mut graphics::Canvas, ctx: &mut Context) -> GameResult {
         let title = Text::new(TextFragment {
             text: "Maze Wars".to_string(),
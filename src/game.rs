@@ -1,9 +1,14 @@
 use std::cmp;
-use std::collections::HashMap;
+use std::collections::{HashMap, VecDeque};
+use std::time::Instant;
 
+use crate::chat::ChatLog;
 use crate::map::Map;
 use crate::player::Direction;
 use crate::player::Player;
+use crate::protocol::{quantise, PlayerId};
+use crate::server::{Message, TICK_INTERVAL};
+use crate::snapshot::{Snapshot, World};
 use crate::SCREEN_WIDTH;
 use crate::VIEWPORT_HEIGHT;
 use crate::VIEWPORT_WIDTH;
@@ -19,6 +24,18 @@ use ggez::{Context, GameResult};
 
 const X: f32 = (SCREEN_WIDTH - VIEWPORT_WIDTH) / 2.0;
 const Y: f32 = 20.0;
+// opponents are drawn this many ticks in the past, between the last two snapshots
+const INTERPOLATION_TICKS: f32 = 1.0;
+// moves further than this are respawns, they are not slid across the map
+const MAX_INTERPOLATED_DISTANCE: f32 = 1.5;
+// unconfirmed moves kept for reconciliation
+const MAX_PREDICTED_MOVES: usize = 64;
+
+// the last two known positions of an opponent with the tick they were seen at
+struct Track {
+    from: (u32, Vec2),
+    to: (u32, Vec2),
+}
 
 pub struct GameStruct {
     pub map: Map, // 17 x 33
@@ -32,7 +49,14 @@ pub struct GameStruct {
     buffer: Vec<f32>,
     score_list: (Text, Text, Mesh),
     closest_opponent: Option<usize>,
-    bullet:Option<(Mesh,f32, f32)>
+    bullet:Option<(Mesh,f32, f32)>,
+    tracks: HashMap<PlayerId, Track>,
+    server_clock: Option<(u32, Instant)>, // newest tick and when it arrived
+    predicted: VecDeque<(u32, (u16, u16), Direction)>, // our moves the server did not confirm yet
+    next_move: u32,
+    pub chat: ChatLog,
+    pub spectator: bool,
+    pub watching: Option<PlayerId>, // whose view a spectator sees, None for the map overview
 }
 
 impl GameStruct {
@@ -40,7 +64,7 @@ impl GameStruct {
         let score_list = GameStruct::create_player_list(ctx, &player_name, &map);
         Ok(Self {
             map,
-            player: Player::new(player_name, player_pos),
+            player: Player::new(0, player_name, player_pos), // id is set when the server welcomes us
             opponents: vec![],
             opponent_img: GameStruct::upload_opponet_images(ctx),
             players_last_pos: Vec2 { x: 0.0, y: 0.0 },
@@ -50,37 +74,185 @@ impl GameStruct {
             score_list,
             closest_opponent: None,
             bullet:None,
+            tracks: HashMap::new(),
+            server_clock: None,
+            predicted: VecDeque::new(),
+            next_move: 1,
+            chat: ChatLog::default(),
+            spectator: false,
+            watching: None,
         })
     }
-    pub fn remove_player(&mut self, player:String){
+    // a new session, after a host migration the server counts its ticks from scratch
+    // and does not know our moves
+    pub fn resync(&mut self) {
+        self.server_clock = None;
+        self.tracks.clear();
+        self.predicted.clear();
+    }
+    pub fn remove_player(&mut self, player:PlayerId){
+        self.tracks.remove(&player);
         for i in 0..self.opponents.len(){
-            if self.opponents[i].name == player{
-                self.opponents[i].pos= Vec2{x:0.0,y:16.0};
+            if self.opponents[i].id == player{
+                self.opponents[i].active = false;
             }
         }
     }
-    pub fn register_shooting(&mut self, shot_data: (String, String))->bool {
+    // scores change right away, the server respawns the target and its snapshots have the final say
+    pub fn register_shooting(&mut self, shot_data: (PlayerId, PlayerId)) {
         let shooter = shot_data.0;
         let target = shot_data.1;
+        let kill = format!("{} shot {}", self.name_of(shooter), self.name_of(target));
+        self.chat.system(kill);
         for player in self.opponents.iter_mut() {
-            if player.name == shooter {
+            if player.id == shooter {
                 player.shot_opponent();
-            } else if player.name == target {
+            } else if player.id == target {
                 player.got_shot();
             }
         }
-        if self.player.name == shooter {
+        if self.player.id == shooter {
             self.player.shot_opponent()
-        } else if self.player.name == target {
+        } else if self.player.id == target {
             self.player.got_shot();
-            let new_loc = self.map.get_random_location();
-            self.player.pos.x = new_loc.0;
-            self.player.pos.y = new_loc.1;
-            return true
         }
-        return false
+    }
+    pub fn name_of(&self, id: PlayerId) -> String {
+        if self.player.id == id {
+            return self.player.name.clone();
+        }
+        self.opponents
+            .iter()
+            .find(|opponent| opponent.id == id)
+            .map_or(format!("Player {}", id), |opponent| opponent.name.clone())
+    }
+    // the move the player just made, kept until the server confirms it
+    pub fn prepare_move(&mut self) -> Message {
+        let seq = self.next_move;
+        self.next_move += 1;
+        let cell = quantise(self.player.pos);
+        if self.predicted.len() == MAX_PREDICTED_MOVES {
+            self.predicted.pop_front();
+        }
+        self.predicted.push_back((seq, cell, self.player.dir.clone()));
+        Message::PlayerMoved(self.player.id, cell, self.player.dir.clone(), seq)
+    }
+    // the servers world, players missing from it are gone
+    pub fn apply_snapshot(&mut self, snapshot: &Snapshot) {
+        let world = match snapshot.apply(&World::new()) {
+            Some(world) => world,
+            None => return,
+        };
+        if self.server_clock.is_some_and(|(tick, _)| snapshot.tick <= tick) {
+            return;
+        }
+        self.server_clock = Some((snapshot.tick, Instant::now()));
+        if let Some(state) = world.get(&self.player.id) {
+            self.player.score = state.score;
+            self.reconcile(snapshot.last_move, state.pos, &state.dir);
+        }
+        let mut gone = vec![];
+        for opponent in self.opponents.iter_mut() {
+            match world.get(&opponent.id) {
+                Some(state) => {
+                    // a lost player that resumed
+                    opponent.active = true;
+                    opponent.pos = Vec2::new(state.pos.0 as f32, state.pos.1 as f32);
+                    opponent.dir = state.dir.clone();
+                    opponent.score = state.score;
+                    let seen = (snapshot.tick, opponent.pos);
+                    let track = self.tracks.entry(opponent.id).or_insert(Track {
+                        from: seen,
+                        to: seen,
+                    });
+                    track.from = track.to;
+                    track.to = seen;
+                    if track.from.1.distance(track.to.1) > MAX_INTERPOLATED_DISTANCE {
+                        track.from = seen;
+                    }
+                }
+                None => gone.push(opponent.id),
+            }
+        }
+        for id in gone {
+            self.remove_player(id);
+        }
+    }
+    // Our own moves are shown right away. When the server saw us somewhere else after
+    // the last move it confirmed, it rejected a move and we go back to where it says.
+    fn reconcile(&mut self, last_move: u32, pos: (u16, u16), dir: &Direction) {
+        while self.predicted.front().is_some_and(|(seq, _, _)| *seq < last_move) {
+            self.predicted.pop_front();
+        }
+        let expected = match self.predicted.front() {
+            Some((seq, cell, dir)) if *seq == last_move => (*cell, dir.clone()),
+            // everything is confirmed, the server should see us where we are
+            None => (quantise(self.player.pos), self.player.dir.clone()),
+            // the confirmed move is not known anymore, wait for the next one
+            Some(_) => return,
+        };
+        if expected != (pos, dir.clone()) {
+            println!("Server corrected our position to {:?}", pos);
+            self.player.pos = Vec2::new(pos.0 as f32, pos.1 as f32);
+            self.player.dir = dir.clone();
+            self.predicted.clear();
+        }
+    }
+    // where an opponent is drawn, between the last two snapshots it was in
+    fn interpolated_pos(&self, opponent: &Player) -> Vec2 {
+        let (track, (tick, received)) = match (self.tracks.get(&opponent.id), self.server_clock) {
+            (Some(track), Some(clock)) => (track, clock),
+            _ => return opponent.pos,
+        };
+        if track.from.0 == track.to.0 {
+            return track.to.1;
+        }
+        let tick_secs = TICK_INTERVAL.as_secs_f32();
+        let now = tick as f32 + received.elapsed().as_secs_f32() / tick_secs;
+        let render_tick = now - INTERPOLATION_TICKS;
+        let t = (render_tick - track.from.0 as f32) / (track.to.0 - track.from.0) as f32;
+        track.from.1.lerp(track.to.1, t.clamp(0.0, 1.0))
+    }
+    pub fn reset_scores(&mut self) {
+        self.player.score = 0;
+        for opponent in self.opponents.iter_mut() {
+            opponent.score = 0;
+        }
+    }
+    // players that are in the match right now
+    fn active_opponents(&self) -> Vec<&Player> {
+        self.opponents
+            .iter()
+            .filter(|opponent| opponent.active)
+            .collect()
+    }
+    // the spectator goes through the players by id and back to the overview
+    pub fn watch_next(&mut self) {
+        let mut ids: Vec<PlayerId> = self.active_opponents().iter().map(|o| o.id).collect();
+        ids.sort();
+        self.watching = match self.watching {
+            None => ids.first().copied(),
+            Some(current) => ids.into_iter().find(|id| *id > current),
+        };
+    }
+    // the spectators camera sits on the watched player
+    fn follow_watched(&mut self) {
+        let watched = self
+            .watching
+            .and_then(|id| self.active_opponents().into_iter().find(|o| o.id == id))
+            .map(|opponent| (self.interpolated_pos(opponent), opponent.dir.clone()));
+        match watched {
+            Some((pos, dir)) => {
+                self.player.pos = pos;
+                self.player.dir = dir;
+            }
+            None => self.watching = None,
+        }
     }
     pub fn update(&mut self) -> GameResult {
+        if self.spectator {
+            self.follow_watched();
+        }
         // Update scene
         if self.players_last_pos != self.player.pos || self.player.dir != self.players_last_dir {
             self.trace_scene()?;
@@ -118,7 +290,7 @@ impl GameStruct {
         for (i, score) in self.score_list.1.fragments_mut().iter_mut().enumerate() {
             if i == i_active + 1 {
                 score.color = Some(Color::WHITE);
-            } else if i!=0 && self.opponents.len()!=0 && self.opponents[i - 1].pos == (Vec2{x:0.0,y:16.0}){
+            } else if i!=0 && self.opponents.len()!=0 && !self.opponents[i - 1].active{
                 score.color = Some(Color::from_rgb(190,190,190));
             }else{
                 score.color = Some(Color::BLACK);
@@ -127,7 +299,7 @@ impl GameStruct {
         for (i, name) in self.score_list.0.fragments_mut().iter_mut().enumerate() {
             if i == i_active + 1 {
                 name.color = Some(Color::WHITE);
-            }  else if i!=0 && self.opponents.len()!=0 && self.opponents[i - 1].pos == (Vec2{x:0.0,y:16.0}){
+            }  else if i!=0 && self.opponents.len()!=0 && !self.opponents[i - 1].active{
                 name.color = Some(Color::from_rgb(190,190,190));
             }else{
                 name.color = Some(Color::BLACK);
@@ -136,26 +308,19 @@ impl GameStruct {
     }
 
     fn update_closest_opponent(&mut self) {
-        let mut distance = 1.0;
-        let maze = &self.map.maze;
-        let direction = self.player.dir.vec();
-        loop {
-            let square = self.player.pos + direction * distance;
-            if maze[square.y as usize][square.x as usize] == 0 {
-                for (i, opponent) in self.opponents.iter().enumerate() {
-                    if opponent.pos == square {
-                        self.closest_opponent = Some(i);
-                        return;
-                    }
-                }
-            } else {
-                self.closest_opponent = None;
-                return;
-            }
-            distance += 1.0
+        if self.spectator {
+            self.closest_opponent = None;
+            return;
         }
+        // an index into all opponents, the score list highlights it
+        let active = self.active_opponents();
+        let closest = self
+            .player
+            .first_in_line_of_sight(&self.map.maze, active.iter().copied())
+            .and_then(|i| self.opponents.iter().position(|opponent| opponent.id == active[i].id));
+        self.closest_opponent = closest;
     }
-    pub fn shoot(&mut self, ctx: &mut Context) -> Option<(String, String)> {
+    pub fn shoot(&mut self, ctx: &mut Context) -> Option<(PlayerId, PlayerId)> {
         // shoot the bullet
         self.bullet = Some((Mesh::new_rectangle(ctx, DrawMode::fill(), Rect::new(0., 0., 10.0, 10.0), Color::BLACK).unwrap(), 20.+ VIEWPORT_HEIGHT,1.));
 
@@ -163,7 +328,7 @@ impl GameStruct {
             return None;
         }
         let i = self.closest_opponent.unwrap();
-        Some((self.player.name.clone(), self.opponents[i].name.clone()))
+        Some((self.player.id, self.opponents[i].id))
     }
     fn create_player_list(
         ctx: &mut Context,
@@ -219,20 +384,38 @@ impl GameStruct {
     }
     pub fn draw(&mut self, canvas: &mut graphics::Canvas, ctx: &mut Context) -> GameResult {
         self.map.draw(canvas)?;
-        self.map.draw_player_position(canvas, &self.player)?;
-        // Helper for displaying opponents on map
-        // self.map.draw_opponents(ctx, canvas, &self.opponents)?;
-
         self.draw_fps_counter(canvas, ctx)?;
-        //draw 3D scene
-        let mesh = Mesh::from_data(ctx, self.scene.build());
-        canvas.draw(&mesh, DrawParam::default());
-        self.draw_bullet(canvas)?;
-        self.draw_opponents(canvas)?;
+        if self.spectator && self.watching.is_none() {
+            // map overview with every player on it
+            self.map.draw_opponents(ctx, canvas, &self.active_opponents())?;
+            self.draw_spectator_caption(canvas, "Map overview, press Tab to watch a player".to_string());
+        } else {
+            self.map.draw_player_position(canvas, &self.player)?;
+            //draw 3D scene
+            let mesh = Mesh::from_data(ctx, self.scene.build());
+            canvas.draw(&mesh, DrawParam::default());
+            self.draw_bullet(canvas)?;
+            self.draw_opponents(canvas)?;
+            if let Some(id) = self.watching {
+                self.draw_spectator_caption(canvas, format!("Watching {}, press Tab for the next", self.name_of(id)));
+            }
+        }
         self.draw_opponent_list(canvas)?;
+        // the chat log fills the space under the score list
+        let (x, y, len) = self.map.get_map_corner_and_len();
+        let top = y + 20. + self.score_list.0.measure(ctx)?.y + 10.;
+        self.chat.draw(canvas, ctx, x, top, len + 10.)?;
 
         Ok(())
     }
+    fn draw_spectator_caption(&self, canvas: &mut graphics::Canvas, caption: String) {
+        let mut text = Text::new(TextFragment::new(caption).color(Color::BLACK));
+        text.set_layout(TextLayout {
+            v_align: TextAlign::Begin,
+            h_align: TextAlign::Middle,
+        });
+        canvas.draw(&text, DrawParam::default().dest([SCREEN_WIDTH / 2., Y + VIEWPORT_HEIGHT + 2.]));
+    }
     fn draw_bullet(&mut self, canvas: &mut graphics::Canvas) -> GameResult{
         if self.bullet.is_none(){
             return Ok(())        
@@ -249,8 +432,12 @@ impl GameStruct {
         let mut visible_opponents: Vec<(&Image, DrawParam, f32)> = vec![];
 
         for i in 0..self.opponents.len() {
+            // the spectator looks through the eyes of the watched player
+            if Some(self.opponents[i].id) == self.watching || !self.opponents[i].active {
+                continue;
+            }
             //translate sprite position to relative to camera
-            let sprite_pos = self.opponents[i].pos - self.player.pos;
+            let sprite_pos = self.interpolated_pos(&self.opponents[i]) - self.player.pos;
             //transform sprite with the inverse camera matrix
             let camera_plane = self.player.camera_plane();
             let inv_det = 1.0 / (camera_plane.x * player_dir.y - player_dir.x * camera_plane.y);
@@ -378,10 +565,10 @@ impl GameStruct {
                     side = 1;
                 }
                 if map_x as usize >= maze[0].len() {
-                    map_x = 32
+                    map_x = maze[0].len() as i32 - 1
                 }
                 if map_y as usize >= maze.len(){
-                    map_y = 16
+                    map_y = maze.len() as i32 - 1
                 }
                 if maze[map_y as usize][map_x as usize] > 0 {
                     hit = 1;
@@ -501,16 +688,26 @@ impl GameStruct {
         canvas.draw(&text, DrawParam::default());
         Ok(())
     }
-    pub fn add_opponents(&mut self, list: Vec<String>) {
-        for player_name in list.iter() {
-            if player_name.to_owned() != self.player.name {
-                let opponent = Player::new(player_name.to_string(), (0.,0.));
+    pub fn add_opponents(&mut self, list: Vec<(PlayerId, String)>) {
+        // a resumed session gets the list again, a resumed opponent is back in the match
+        for opponent in self.opponents.iter_mut() {
+            if list.iter().any(|(id, _)| *id == opponent.id) {
+                opponent.active = true;
+            }
+        }
+        let list: Vec<(PlayerId, String)> = list
+            .into_iter()
+            .filter(|(id, _)| self.opponents.iter().all(|opponent| opponent.id != *id))
+            .collect();
+        for (id, player_name) in list.iter() {
+            if *id != self.player.id {
+                let opponent = Player::new(*id, player_name.to_string(), (0.,0.));
                 self.opponents.push(opponent);
             }
         }
         // opponents in  score_list
-        for player_name in list.iter() {
-            if player_name.to_owned() != self.player.name {
+        for (id, player_name) in list.iter() {
+            if *id != self.player.id {
                 self.score_list
                     .0
                     .add(TextFragment::new(format!("{:11}", player_name)).color(Color::BLACK));
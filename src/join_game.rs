@@ -1,24 +1,32 @@
 use std::collections::HashMap;
+use std::net::SocketAddr;
 
 use ggez::{
-    graphics::{self, Rect, Text},
+    graphics::{self, Color, DrawParam, Rect, Text, TextAlign, TextLayout},
     Context, GameResult,
 };
 
 use crate::{
     drawer::{Button, Drawer, Input},
-    view::View, state::Map,
+    server::resolve_address,
+    view::View,
 };
 
-use super::{game::GameStruct, main_menu::MainMenuStruct};
+use super::main_menu::MainMenuStruct;
 
 pub struct JoinGameStruct {
     pub element_rects: HashMap<String, Rect>, // holds text input and button rects
     pub ip_input_active: bool,
     pub name_input_active: bool,
+    pub password_input_active: bool,
     pub ip_address: Text,
     pub name: Text,
+    pub password: Text, // empty for servers without one
     pub drawer: Drawer,
+    pub error: Option<String>,
+    pub server_address: Option<SocketAddr>, // resolved when join is clicked
+    pub connecting: bool, // waiting for the server to accept us and send the map
+    pub spectator: bool,  // watch the match instead of playing
 }
 
 impl JoinGameStruct {
@@ -28,9 +36,15 @@ impl JoinGameStruct {
             element_rects: Self::get_elements(&drawer.button_dimensions, &drawer.input_dimensions),
             ip_input_active: false,
             name_input_active: false,
+            password_input_active: false,
             ip_address: Text::new(""),
             name: Text::new(""),
+            password: Text::new(""),
             drawer,
+            error: None,
+            server_address: None,
+            connecting: false,
+            spectator: false,
         })
     }
     pub fn paste_value(&mut self, value:String){
@@ -38,6 +52,8 @@ impl JoinGameStruct {
             self.ip_address = Text::new(value);
         }else if self.name_input_active{
             self.name= Text::new(value);
+        }else if self.password_input_active{
+            self.password = Text::new(value);
         }
     }
     pub fn get_elements(
@@ -65,11 +81,30 @@ impl JoinGameStruct {
                 input_dimensions.height,
             ),
         );
+        elems.insert(
+            "PASSWORD_INPUT".to_string(),
+            Rect::new(
+                input_dimensions.horizontal_offset,
+                200.0 + (input_gap * 2.0),
+                input_dimensions.width,
+                input_dimensions.height,
+            ),
+        );
         elems.insert(
             "JOIN_GAME".to_string(),
             Rect::new(
                 button_dimensions.horizontal_offset,
-                200.0 + (input_gap * 2.0),
+                200.0 + (input_gap * 3.0),
+                button_dimensions.width,
+                button_dimensions.height,
+            ),
+        );
+
+        elems.insert(
+            "SPECTATE".to_string(),
+            Rect::new(
+                button_dimensions.horizontal_offset,
+                200.0 + (input_gap * 3.0) + 50.0,
                 button_dimensions.width,
                 button_dimensions.height,
             ),
@@ -83,7 +118,29 @@ impl JoinGameStruct {
         elems
     }
 
+    pub fn draw_error_message(&self, canvas: &mut graphics::Canvas, error: &str) {
+        self.draw_status(canvas, error, Color::RED);
+    }
+    // one line of text under the join buttons
+    fn draw_status(&self, canvas: &mut graphics::Canvas, status: &str, color: Color) {
+        let mut text = Text::new(status);
+        let join_game_btn_rect = self.element_rects.get("SPECTATE").unwrap();
+        let text_x = join_game_btn_rect.x + join_game_btn_rect.w / 2.0;
+        let text_y = join_game_btn_rect.y + join_game_btn_rect.h + 10.0;
+        text.set_layout(TextLayout {
+            v_align: TextAlign::Begin,
+            h_align: TextAlign::Middle,
+        });
+
+        canvas.draw(&text, DrawParam::from([text_x, text_y]).color(color));
+    }
+
     pub fn draw(&self, canvas: &mut graphics::Canvas, ctx: &mut Context) -> GameResult {
+        if self.connecting {
+            self.draw_status(canvas, "Connecting...", Color::BLACK)
+        } else if let Some(error) = &self.error {
+            self.draw_error_message(canvas, error)
+        }
         self.drawer.draw_title(canvas, ctx)?;
         self.drawer.draw_name_input(
             canvas,
@@ -103,6 +160,15 @@ impl JoinGameStruct {
             *self.element_rects.get("IP_INPUT").unwrap(),
         )?;
 
+        self.drawer.draw_password_input(
+            canvas,
+            ctx,
+            350.0,
+            self.password_input_active,
+            self.password.contents(),
+            *self.element_rects.get("PASSWORD_INPUT").unwrap(),
+        )?;
+
         self.drawer.draw_back_arrow_img(
             canvas,
             ctx,
@@ -112,9 +178,16 @@ impl JoinGameStruct {
         self.drawer.draw_join_game_button(
             canvas,
             ctx,
-            350.0,
+            425.0,
             *self.element_rects.get("JOIN_GAME").unwrap(),
         )?;
+        self.drawer.draw_button(
+            canvas,
+            ctx,
+            475.0,
+            *self.element_rects.get("SPECTATE").unwrap(),
+            "Spectate",
+        )?;
         self.drawer
             .draw_fps_counter(canvas, ctx)
             .expect("Cant draw fps counter.");
@@ -136,19 +209,33 @@ impl JoinGameStruct {
             {
                 if name == "IP_INPUT" {
                     self.ip_input_active = true;
-                    if self.name_input_active {
-                        self.name_input_active = false;
-                    }
+                    self.name_input_active = false;
+                    self.password_input_active = false;
                 } else if name == "NAME_INPUT" {
                     self.name_input_active = true;
-                    if self.ip_input_active {
-                        self.ip_input_active = false;
+                    self.ip_input_active = false;
+                    self.password_input_active = false;
+                } else if name == "PASSWORD_INPUT" {
+                    self.password_input_active = true;
+                    self.ip_input_active = false;
+                    self.name_input_active = false;
+                } else if name == "JOIN_GAME" || name == "SPECTATE" {
+                    if self.connecting {
+                        break;
+                    }
+                    self.spectator = name == "SPECTATE";
+                    if self.name.contents().is_empty() {
+                        self.error = Some("Enter your name first".to_string());
+                        break;
+                    }
+                    self.server_address = resolve_address(&self.ip_address.contents());
+                    if self.server_address.is_none() {
+                        self.error = Some("Invalid server address".to_string());
+                        break;
                     }
-                } else if name == "JOIN_GAME" {
-                    let player_name = self.name.contents();
-                    let temp_map = vec![vec![1;33];17];
-                    let map = Map::new(ctx, temp_map);
-                    new_view = Some(View::Game(GameStruct::new(ctx, player_name, map, (0.,0.)).unwrap()));
+                    // the state connects and opens the game once the map arrived
+                    self.error = None;
+                    self.connecting = true;
                     break;
                 } else if name == "BACK_ARROW_IMG" {
                     new_view = Some(View::MainMenu(MainMenuStruct::new(ctx).unwrap()));
@@ -0,0 +1,43 @@
+pub mod admin;
+pub mod bot;
+#[cfg(feature = "gui")]
+pub mod chat;
+pub mod client;
+#[cfg(feature = "gui")]
+pub mod create_game;
+pub mod discovery;
+#[cfg(feature = "gui")]
+pub mod drawer;
+#[cfg(feature = "gui")]
+pub mod game;
+#[cfg(feature = "gui")]
+pub mod join_game;
+#[cfg(feature = "gui")]
+pub mod main_menu;
+#[cfg(feature = "gui")]
+pub mod create_map;
+#[cfg(feature = "gui")]
+pub mod map;
+pub mod map_transfer;
+pub mod maze;
+pub mod password;
+pub mod player;
+pub mod protocol;
+pub mod rate_limit;
+pub mod reliability;
+pub mod replay;
+#[cfg(feature = "gui")]
+pub mod replay_browser;
+pub mod server;
+#[cfg(feature = "gui")]
+pub mod server_browser;
+pub mod snapshot;
+#[cfg(feature = "gui")]
+pub mod state;
+pub mod transport;
+#[cfg(feature = "gui")]
+pub mod view;
+pub const SCREEN_WIDTH: f32 = 600.0;
+pub const SCREEN_HEIGHT: f32 = 800.0;
+pub const VIEWPORT_WIDTH: f32 = 370.0;
+pub const VIEWPORT_HEIGHT: f32 = 410.0;
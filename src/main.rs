@@ -1,23 +1,8 @@
 use ggez::conf::{Conf, WindowMode, WindowSetup};
 use ggez::event;
 use ggez::{ContextBuilder, GameResult};
-use state::State;
-pub mod client;
-pub mod create_game;
-pub mod drawer;
-pub mod game;
-pub mod join_game;
-pub mod main_menu;
-pub mod create_map;
-pub mod map;
-pub mod player;
-pub mod server;
-pub mod state;
-pub mod view;
-const SCREEN_WIDTH: f32 = 600.0;
-const SCREEN_HEIGHT: f32 = 800.0;
-const VIEWPORT_WIDTH: f32 = 370.0;
-const VIEWPORT_HEIGHT: f32 = 410.0;
+use multiplayer_fps::state::State;
+use multiplayer_fps::{SCREEN_HEIGHT, SCREEN_WIDTH};
 
 fn main() -> GameResult {
     let c = Conf::new();
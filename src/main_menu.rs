@@ -7,7 +7,8 @@ use ggez::{
 
 use crate::{
     drawer::{Button, Drawer},
-    view::View, create_map::CreateMap,
+    view::View, create_map::CreateMap, server_browser::ServerBrowserStruct,
+    replay_browser::ReplayBrowserStruct,
 };
 
 use super::{create_game::CreateGameStruct, join_game::JoinGameStruct};
@@ -57,6 +58,24 @@ impl MainMenuStruct {
                 button_dimensions.height,
             ),
         );
+        elems.insert(
+            "FIND_GAMES".to_string(),
+            graphics::Rect::new(
+                button_dimensions.horizontal_offset,
+                200.0 + buttons_gap * 3.,
+                button_dimensions.width,
+                button_dimensions.height,
+            ),
+        );
+        elems.insert(
+            "REPLAYS".to_string(),
+            graphics::Rect::new(
+                button_dimensions.horizontal_offset,
+                200.0 + buttons_gap * 4.,
+                button_dimensions.width,
+                button_dimensions.height,
+            ),
+        );
 
         elems
     }
@@ -81,6 +100,20 @@ impl MainMenuStruct {
             350.0,
             *self.element_rects.get("CREATE_MAP").unwrap(),
         )?;
+        self.drawer.draw_button(
+            canvas,
+            ctx,
+            425.0,
+            *self.element_rects.get("FIND_GAMES").unwrap(),
+            "Find games",
+        )?;
+        self.drawer.draw_button(
+            canvas,
+            ctx,
+            500.0,
+            *self.element_rects.get("REPLAYS").unwrap(),
+            "Replays",
+        )?;
         self.drawer
             .draw_eye(canvas, ctx)
             .expect("Cant draw eye picture.");
@@ -114,6 +147,10 @@ impl MainMenuStruct {
                     // Comment in for normal work!
                 }else if name == "CREATE_MAP" {
                     new_view = Some(View::CreateMap(CreateMap::new(ctx).unwrap()));
+                } else if name == "FIND_GAMES" {
+                    new_view = Some(View::ServerBrowser(ServerBrowserStruct::new(ctx).unwrap()));
+                } else if name == "REPLAYS" {
+                    new_view = Some(View::Replays(ReplayBrowserStruct::new(ctx).unwrap()));
                 }
             }
         }
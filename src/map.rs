@@ -1,7 +1,4 @@
-use std::fs::File;
-use std::io::{BufReader, BufRead};
-use rand::{thread_rng, Rng};
-
+use crate::maze::{random_location, read_maze};
 use crate::player::Player;
 use crate::{SCREEN_WIDTH, VIEWPORT_HEIGHT};
 use ggez::graphics::{DrawMode, MeshBuilder};
@@ -19,46 +16,33 @@ pub struct Map {
     pub width:f32,
     pub height:f32,
 }
-// Map size 33X17
+// Default map size 33X17, bigger maps are drawn with smaller tiles
+const MAX_TILE_SIZE: f32 = 9.;
+const MAX_MAP_HEIGHT: f32 = 160.;
 impl Map {
     pub fn new(ctx: &mut Context, maze:Vec<Vec<i32>>) -> Self {
         let player_arrow = Image::from_path(ctx, "/arrow.png").expect("Arrow image missing");
+        let width = maze.first().map_or(0, |row| row.len()) as f32;
+        let height = maze.len() as f32;
+        let tile_size = MAX_TILE_SIZE
+            .min((SCREEN_WIDTH - 40.) / width)
+            .min(MAX_MAP_HEIGHT / height);
         let mut map = Map {
             maze,
             graphics:None,
             player_arrow,
-            tile_size:9.,
-            width:33.,
-            height:17.,
+            tile_size,
+            width,
+            height,
         };
         map.register_graphics(ctx);
         map
     }
-    pub fn get_random_location(&self)->(f32,f32){
-        let mut  tries = 0;
-        loop{
-            if tries == 1000{panic!("Cant find free space in map")}
-        let mut rng = thread_rng();
-
-        let y: usize = rng.gen_range(1..=15);
-        let x: usize = rng.gen_range(1..=32);
-        if self.maze[y][x] == 0{
-            return (x as f32, y as f32);
-        }
-        tries +=1
-        }
+    pub fn get_random_location(&self)->Option<(f32,f32)>{
+        random_location(&self.maze)
     }
-    pub fn make_from_file(ctx: &mut Context, path:&String)->Map{
-        let input = File::open(path).expect("Map not found");
-        let buffered = BufReader::new(input);
-        let mut map = vec![];
-        for line in buffered.lines() {
-            let mut row = vec![];
-            for tile in line.expect("Invalid line in map").chars(){
-                row.push((tile.to_string()).parse::<i32>().unwrap());
-            }
-            map.push(row);
-        }
+    pub fn make_from_file(ctx: &mut Context, path:&str)->Map{
+        let map = read_maze(path).expect("Map not found");
         Map::new(ctx, map)
 
     }
@@ -131,7 +115,7 @@ impl Map {
         &self,
         ctx: &mut Context,
         canvas: &mut graphics::Canvas,
-        opponents: &Vec<Player>,
+        opponents: &[&Player],
     ) -> GameResult {
         for player in opponents.iter() {
             let (x, y) = self.get_coordinates_for_pos(&player.pos);
@@ -0,0 +1,172 @@
+// cells per chunk, keeps every chunk well below the 2048 byte receive buffers
+pub const CHUNK_CELLS: usize = 400;
+
+// One numbered piece of the maze, the maze is sent row after row as a flat list of tiles
+#[derive(Debug, Clone)]
+pub struct MapChunk {
+    pub index: usize,
+    pub total: usize,
+    pub width: usize,
+    pub height: usize,
+    pub checksum: u32, // of the whole maze, checked after the last chunk
+    pub cells: Vec<i32>,
+}
+
+pub fn split_map(maze: &[Vec<i32>]) -> Vec<MapChunk> {
+    let height = maze.len();
+    let width = maze.first().map(|row| row.len()).unwrap_or(0);
+    let checksum = checksum(maze);
+    let cells: Vec<i32> = maze.iter().flatten().copied().collect();
+    let total = cells.len().div_ceil(CHUNK_CELLS).max(1);
+    (0..total)
+        .map(|index| {
+            let start = index * CHUNK_CELLS;
+            let end = (start + CHUNK_CELLS).min(cells.len());
+            MapChunk {
+                index,
+                total,
+                width,
+                height,
+                checksum,
+                cells: cells[start..end].to_vec(),
+            }
+        })
+        .collect()
+}
+
+// Adler-32 over the tiles and the dimensions
+pub fn checksum(maze: &[Vec<i32>]) -> u32 {
+    const MOD: u32 = 65521;
+    let (mut a, mut b) = (1u32, 0u32);
+    let dimensions = [
+        maze.len() as i32,
+        maze.first().map_or(0, |r| r.len()) as i32,
+    ];
+    for value in dimensions.iter().chain(maze.iter().flatten()) {
+        for byte in value.to_le_bytes() {
+            a = (a + byte as u32) % MOD;
+            b = (b + a) % MOD;
+        }
+    }
+    (b << 16) | a
+}
+
+#[derive(Debug)]
+pub enum MapTransferError {
+    Checksum { expected: u32, actual: u32 },
+    Size,
+}
+
+// Collects the chunks of one map on the client
+#[derive(Default)]
+pub struct MapAssembly {
+    chunks: Vec<Option<Vec<i32>>>,
+    width: usize,
+    height: usize,
+    checksum: u32,
+}
+
+impl MapAssembly {
+    // returns the maze once every chunk has arrived
+    pub fn add(&mut self, chunk: MapChunk) -> Option<Result<Vec<Vec<i32>>, MapTransferError>> {
+        // a chunk that does not fit the current transfer starts a new one
+        if chunk.index == 0 || self.chunks.len() != chunk.total || self.checksum != chunk.checksum {
+            *self = MapAssembly {
+                chunks: vec![None; chunk.total],
+                width: chunk.width,
+                height: chunk.height,
+                checksum: chunk.checksum,
+            };
+        }
+        if chunk.index >= self.chunks.len() {
+            return None;
+        }
+        self.chunks[chunk.index] = Some(chunk.cells);
+        if self.chunks.iter().any(|chunk| chunk.is_none()) {
+            return None;
+        }
+
+        let cells: Vec<i32> = self.chunks.drain(..).flatten().flatten().collect();
+        if self.width == 0 || cells.len() != self.width * self.height {
+            return Some(Err(MapTransferError::Size));
+        }
+        let maze: Vec<Vec<i32>> = cells.chunks(self.width).map(|row| row.to_vec()).collect();
+        let actual = checksum(&maze);
+        if actual != self.checksum {
+            return Some(Err(MapTransferError::Checksum {
+                expected: self.checksum,
+                actual,
+            }));
+        }
+        Some(Ok(maze))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // walls around the border and a few inside, every map of the tests differs
+    fn maze(width: usize, height: usize, seed: usize) -> Vec<Vec<i32>> {
+        (0..height)
+            .map(|y| {
+                (0..width)
+                    .map(|x| {
+                        let border = x == 0 || y == 0 || x == width - 1 || y == height - 1;
+                        (border || (x * 7 + y * 3 + seed) % 5 == 0) as i32
+                    })
+                    .collect()
+            })
+            .collect()
+    }
+
+    fn assemble(chunks: Vec<MapChunk>) -> Option<Result<Vec<Vec<i32>>, MapTransferError>> {
+        let mut assembly = MapAssembly::default();
+        let mut last = None;
+        for chunk in chunks {
+            assert!(last.is_none(), "the map was done before the last chunk");
+            last = assembly.add(chunk);
+        }
+        last
+    }
+
+    #[test]
+    fn large_map_comes_back_whole() {
+        let maze = maze(40, 30, 0);
+        let chunks = split_map(&maze);
+        assert_eq!(chunks.len(), 3);
+        assert!(chunks.iter().all(|chunk| chunk.cells.len() <= CHUNK_CELLS));
+        assert_eq!(assemble(chunks).unwrap().unwrap(), maze);
+    }
+
+    #[test]
+    fn restarted_transfer_is_assembled_from_scratch() {
+        let (old, new) = (maze(40, 30, 0), maze(40, 30, 1));
+        let mut assembly = MapAssembly::default();
+        let old_chunks = split_map(&old);
+        assert!(assembly.add(old_chunks[0].clone()).is_none());
+        assert!(assembly.add(old_chunks[1].clone()).is_none());
+        // the map changed before the old one was through
+        let mut result = None;
+        for chunk in split_map(&new) {
+            result = assembly.add(chunk);
+        }
+        assert_eq!(result.unwrap().unwrap(), new);
+    }
+
+    #[test]
+    fn broken_tile_fails_the_checksum() {
+        let maze = maze(40, 30, 0);
+        let mut chunks = split_map(&maze);
+        chunks[1].cells[5] ^= 1;
+        let result = assemble(chunks).unwrap();
+        assert!(matches!(result, Err(MapTransferError::Checksum { expected, .. }) if expected == checksum(&maze)));
+    }
+
+    #[test]
+    fn missing_tiles_fail_the_size() {
+        let mut chunks = split_map(&maze(40, 30, 0));
+        chunks[2].cells.pop();
+        assert!(matches!(assemble(chunks).unwrap(), Err(MapTransferError::Size)));
+    }
+}
@@ -0,0 +1,64 @@
+use std::fs::File;
+use std::io::{self, BufRead, BufReader};
+use std::path::Path;
+
+use rand::{thread_rng, Rng};
+
+// The maze as the server sees it, rows of tiles where 0 is free and everything else
+// is wall. Map draws it in the game, these work without any graphics.
+
+// a free cell inside the outer walls, the server spawns players with it,
+// None when there is no such cell
+pub fn random_location(maze: &[Vec<i32>]) -> Option<(f32, f32)> {
+    let free: Vec<(usize, usize)> = maze
+        .iter()
+        .enumerate()
+        .skip(1)
+        .take(maze.len().saturating_sub(2))
+        .flat_map(|(y, row)| {
+            let inside = row.len().saturating_sub(2);
+            row.iter()
+                .enumerate()
+                .skip(1)
+                .take(inside)
+                .filter(|(_, tile)| **tile == 0)
+                .map(move |(x, _)| (x, y))
+        })
+        .collect();
+    if free.is_empty() {
+        return None;
+    }
+    let (x, y) = free[thread_rng().gen_range(0..free.len())];
+    Some((x as f32, y as f32))
+}
+
+// every map a server plays on has to pass this, players spawn with random_location
+pub fn is_playable(maze: &[Vec<i32>]) -> bool {
+    let width = maze.first().map_or(0, |row| row.len());
+    maze.iter().all(|row| row.len() == width) && random_location(maze).is_some()
+}
+
+// map name shown to players, the file name without folder and extension
+pub fn name_from_path(path: &str) -> String {
+    Path::new(path)
+        .file_stem()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default()
+}
+
+pub fn read_maze(path: &str) -> io::Result<Vec<Vec<i32>>> {
+    let input = File::open(path)?;
+    let buffered = BufReader::new(input);
+    let mut map = vec![];
+    for line in buffered.lines() {
+        let mut row = vec![];
+        for tile in line?.chars() {
+            let tile = tile.to_digit(10).ok_or_else(|| {
+                io::Error::new(io::ErrorKind::InvalidData, "Invalid tile in map")
+            })?;
+            row.push(tile as i32);
+        }
+        map.push(row);
+    }
+    Ok(map)
+}
@@ -0,0 +1,82 @@
+// Servers with a password never see it on the wire. The server sends a random nonce,
+// the client answers with the nonce signed with a key made from the password and the
+// server checks that answer with its own copy of the key. Only the key is handed on to
+// a new host, the password itself never leaves the machine it was typed on.
+
+// fixed keys that turn a password into the 128 bit signing key
+const PASSWORD_KEYS: [(u64, u64); 2] = [
+    (0x6d61_7a65_2d77_6172, 0x732d_7061_7373_776f),
+    (0x7264_2d6b_6579_2d30, 0x6d61_7a65_2d6b_6579),
+];
+
+pub type PasswordKey = (u64, u64);
+
+pub fn password_key(password: &str) -> PasswordKey {
+    (
+        siphash(PASSWORD_KEYS[0], password.as_bytes()),
+        siphash(PASSWORD_KEYS[1], password.as_bytes()),
+    )
+}
+
+// the answer to a challenge, never 0 because a Join without an answer carries 0
+pub fn password_proof(key: PasswordKey, nonce: u64) -> u64 {
+    siphash(key, &nonce.to_le_bytes()).max(1)
+}
+
+// SipHash-2-4, with a secret key it works as a message authentication code
+pub fn siphash(key: (u64, u64), data: &[u8]) -> u64 {
+    let mut v = [
+        key.0 ^ 0x736f_6d65_7073_6575,
+        key.1 ^ 0x646f_7261_6e64_6f6d,
+        key.0 ^ 0x6c79_6765_6e65_7261,
+        key.1 ^ 0x7465_6462_7974_6573,
+    ];
+    let mut blocks = data.chunks_exact(8);
+    for block in blocks.by_ref() {
+        let m = u64::from_le_bytes(block.try_into().unwrap());
+        v[3] ^= m;
+        sip_round(&mut v);
+        sip_round(&mut v);
+        v[0] ^= m;
+    }
+    let mut last = [0; 8];
+    last[..blocks.remainder().len()].copy_from_slice(blocks.remainder());
+    let m = u64::from_le_bytes(last) | ((data.len() as u64) << 56);
+    v[3] ^= m;
+    sip_round(&mut v);
+    sip_round(&mut v);
+    v[0] ^= m;
+    v[2] ^= 0xff;
+    for _ in 0..4 {
+        sip_round(&mut v);
+    }
+    v[0] ^ v[1] ^ v[2] ^ v[3]
+}
+
+fn sip_round(v: &mut [u64; 4]) {
+    v[0] = v[0].wrapping_add(v[1]);
+    v[1] = v[1].rotate_left(13) ^ v[0];
+    v[0] = v[0].rotate_left(32);
+    v[2] = v[2].wrapping_add(v[3]);
+    v[3] = v[3].rotate_left(16) ^ v[2];
+    v[0] = v[0].wrapping_add(v[3]);
+    v[3] = v[3].rotate_left(21) ^ v[0];
+    v[2] = v[2].wrapping_add(v[1]);
+    v[1] = v[1].rotate_left(17) ^ v[2];
+    v[2] = v[2].rotate_left(32);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // test vectors from the SipHash paper, key 00..0f and messages 00, 01, .. of growing length
+    #[test]
+    fn siphash_matches_the_reference() {
+        let key = (0x0706_0504_0302_0100, 0x0f0e_0d0c_0b0a_0908);
+        let message: Vec<u8> = (0..15).collect();
+        assert_eq!(siphash(key, &[]), 0x726f_db47_dd0e_0e31);
+        assert_eq!(siphash(key, &message[..8]), 0x93f5_f579_9a93_2462);
+        assert_eq!(siphash(key, &message), 0xa129_ca61_49be_45e5);
+    }
+}
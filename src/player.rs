@@ -1,16 +1,23 @@
-use ggez::{glam::Vec2};
+use glam::Vec2;
 use serde::{Deserialize, Serialize};
 use std::time::Duration;
 use throttle::Throttle;
 use rand::{thread_rng, Rng};
 
+use crate::protocol::PlayerId;
+
+// a player moves or turns at most once per interval
+pub const MOVE_INTERVAL: Duration = Duration::from_millis(100);
+
 pub struct Player {
+    pub id: PlayerId,
     pub name: String,
     pub pos: Vec2,
     pub dir: Direction,
     pub moving_throttle: Throttle,
     pub score: i32,
-    pub can_shoot: bool
+    pub can_shoot: bool,
+    pub active: bool, // false for opponents that left, the game keeps their score
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
@@ -54,14 +61,16 @@ impl Direction {
 }
 
 impl Player {
-    pub fn new(name:String, pos:(f32,f32)) -> Self {
+    pub fn new(id: PlayerId, name:String, pos:(f32,f32)) -> Self {
         Self {
+            id,
             pos: Vec2::new(pos.0, pos.1),
             dir: Direction::random(),
-            moving_throttle: Throttle::new(Duration::from_millis(100), 1),
+            moving_throttle: Throttle::new(MOVE_INTERVAL, 1),
             name,
             score:0,
             can_shoot: true,
+            active: true,
         }
     }
     pub fn got_shot(&mut self){
@@ -149,6 +158,15 @@ impl Player {
         }
         return false
     }
+    // walks the corridor in front of the player and returns the index of the first
+    // player standing in it, stops at the first wall
+    pub fn first_in_line_of_sight<'a>(
+        &self,
+        maze: &[Vec<i32>],
+        players: impl IntoIterator<Item = &'a Player>,
+    ) -> Option<usize> {
+        first_in_line(self.pos, &self.dir, maze, players)
+    }
     pub fn camera_plane(&self) -> Vec2{
         const FOV:f32 = 0.65;
         match self.dir {
@@ -215,3 +233,32 @@ impl Player {
         }
     }
 }
+
+// the line of sight walk of a player standing at pos and looking in dir,
+// bots use it to look around before they turn
+pub fn first_in_line<'a>(
+    pos: Vec2,
+    dir: &Direction,
+    maze: &[Vec<i32>],
+    players: impl IntoIterator<Item = &'a Player>,
+) -> Option<usize> {
+    let players: Vec<&Player> = players.into_iter().collect();
+    let direction = dir.vec();
+    let mut distance = 1.0;
+    loop {
+        let square = pos + direction * distance;
+        if square.x < 0. || square.y < 0. {
+            return None;
+        }
+        let tile = maze
+            .get(square.y as usize)
+            .and_then(|row| row.get(square.x as usize));
+        if tile != Some(&0) {
+            return None;
+        }
+        if let Some(i) = players.iter().position(|player| player.pos == square) {
+            return Some(i);
+        }
+        distance += 1.0
+    }
+}
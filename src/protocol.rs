@@ -0,0 +1,919 @@
+use std::collections::HashSet;
+use std::fmt;
+use std::time::Duration;
+
+use glam::Vec2;
+
+use crate::bot::BotSkill;
+use crate::map_transfer::MapChunk;
+use crate::player::Direction;
+use crate::reliability::Packet;
+use crate::server::{
+    HostState, MigratedPlayer, Message, NetworkError, PlayerStatus, ServerConfig, ServerInfo,
+    ServerStatus,
+};
+use crate::snapshot::{PlayerDelta, Snapshot};
+
+// Binary encoding of everything sent between client and server. A packet starts with
+// its kind, a message with its tag, numbers are little endian and strings and lists
+// are prefixed with their length.
+//
+// Bump PROTOCOL_VERSION whenever the encoding changes. An unreliable Join or Resume always
+// starts with the version and JoinRejected is always a single string, so a server can tell
+// an outdated client why it can not join.
+pub const PROTOCOL_VERSION: u16 = 13;
+
+// assigned by the server when a player joins, used instead of the name on the wire
+pub type PlayerId = u16;
+
+#[derive(Debug)]
+pub enum DecodeError {
+    UnexpectedEnd,
+    UnknownTag(u8),
+    InvalidString,
+    TrailingBytes(usize),
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DecodeError::UnexpectedEnd => write!(f, "datagram ends too early"),
+            DecodeError::UnknownTag(tag) => write!(f, "unknown tag {}", tag),
+            DecodeError::InvalidString => write!(f, "string is not valid utf-8"),
+            DecodeError::TrailingBytes(n) => write!(f, "{} bytes left after the message", n),
+        }
+    }
+}
+
+impl std::error::Error for DecodeError {}
+
+// players stand on whole grid cells, they are sent as cell indices
+pub fn quantise(pos: Vec2) -> (u16, u16) {
+    (pos.x.max(0.).round() as u16, pos.y.max(0.).round() as u16)
+}
+
+#[derive(Default)]
+pub struct Writer {
+    bytes: Vec<u8>,
+}
+
+impl Writer {
+    pub fn new() -> Writer {
+        Writer::default()
+    }
+    pub fn finish(self) -> Vec<u8> {
+        self.bytes
+    }
+    pub fn u8(&mut self, value: u8) {
+        self.bytes.push(value);
+    }
+    pub fn u16(&mut self, value: u16) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+    pub fn u32(&mut self, value: u32) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+    pub fn u64(&mut self, value: u64) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+    pub fn i32(&mut self, value: i32) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+    pub fn string(&mut self, value: &str) {
+        let bytes = &value.as_bytes()[..value.len().min(u16::MAX as usize)];
+        self.u16(bytes.len() as u16);
+        self.bytes.extend_from_slice(bytes);
+    }
+    pub fn direction(&mut self, direction: &Direction) {
+        self.u8(match direction {
+            Direction::Up => 0,
+            Direction::Down => 1,
+            Direction::Left => 2,
+            Direction::Right => 3,
+        });
+    }
+}
+
+pub struct Reader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    pub fn new(bytes: &'a [u8]) -> Reader<'a> {
+        Reader { bytes, pos: 0 }
+    }
+    // fails when anything is left unread
+    pub fn finish(&self) -> Result<(), DecodeError> {
+        match self.bytes.len() - self.pos {
+            0 => Ok(()),
+            left => Err(DecodeError::TrailingBytes(left)),
+        }
+    }
+    pub fn skip_rest(&mut self) {
+        self.pos = self.bytes.len();
+    }
+    pub fn remaining(&self) -> usize {
+        self.bytes.len() - self.pos
+    }
+    fn take(&mut self, n: usize) -> Result<&'a [u8], DecodeError> {
+        if self.bytes.len() - self.pos < n {
+            return Err(DecodeError::UnexpectedEnd);
+        }
+        let taken = &self.bytes[self.pos..self.pos + n];
+        self.pos += n;
+        Ok(taken)
+    }
+    pub fn u8(&mut self) -> Result<u8, DecodeError> {
+        Ok(self.take(1)?[0])
+    }
+    pub fn u16(&mut self) -> Result<u16, DecodeError> {
+        Ok(u16::from_le_bytes(self.take(2)?.try_into().unwrap()))
+    }
+    pub fn u32(&mut self) -> Result<u32, DecodeError> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+    pub fn u64(&mut self) -> Result<u64, DecodeError> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+    pub fn i32(&mut self) -> Result<i32, DecodeError> {
+        Ok(i32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+    pub fn string(&mut self) -> Result<String, DecodeError> {
+        let len = self.u16()? as usize;
+        let bytes = self.take(len)?;
+        String::from_utf8(bytes.to_vec()).map_err(|_| DecodeError::InvalidString)
+    }
+    pub fn direction(&mut self) -> Result<Direction, DecodeError> {
+        match self.u8()? {
+            0 => Ok(Direction::Up),
+            1 => Ok(Direction::Down),
+            2 => Ok(Direction::Left),
+            3 => Ok(Direction::Right),
+            tag => Err(DecodeError::UnknownTag(tag)),
+        }
+    }
+}
+
+impl Packet {
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut w = Writer::new();
+        match self {
+            Packet::Unreliable(msg) => {
+                w.u8(0);
+                msg.encode(&mut w);
+            }
+            Packet::Reliable(seq, msg) => {
+                w.u8(1);
+                w.u32(*seq);
+                msg.encode(&mut w);
+            }
+            Packet::Ack(seq) => {
+                w.u8(2);
+                w.u32(*seq);
+            }
+        }
+        w.finish()
+    }
+    pub fn from_bytes(bytes: &[u8]) -> Result<Packet, NetworkError> {
+        Packet::decode(bytes).map_err(NetworkError::Decode)
+    }
+    fn decode(bytes: &[u8]) -> Result<Packet, DecodeError> {
+        let mut r = Reader::new(bytes);
+        let packet = match r.u8()? {
+            0 => Packet::Unreliable(Message::decode(&mut r)?),
+            1 => {
+                let seq = r.u32()?;
+                Packet::Reliable(seq, Message::decode(&mut r)?)
+            }
+            2 => Packet::Ack(r.u32()?),
+            tag => return Err(DecodeError::UnknownTag(tag)),
+        };
+        r.finish()?;
+        Ok(packet)
+    }
+}
+
+impl Message {
+    pub fn encode(&self, w: &mut Writer) {
+        match self {
+            Message::Join(version, name, proof, spectator) => {
+                w.u8(0);
+                w.u16(*version);
+                w.string(name);
+                w.u64(*proof);
+                w.u8(*spectator as u8);
+            }
+            Message::JoinRejected(reason) => {
+                w.u8(1);
+                w.string(reason);
+            }
+            Message::Welcome((id, token)) => {
+                w.u8(2);
+                w.u16(*id);
+                w.u64(*token);
+            }
+            Message::OpponentList(list) => {
+                w.u8(3);
+                w.u16(list.len() as u16);
+                for (id, name) in list {
+                    w.u16(*id);
+                    w.string(name);
+                }
+            }
+            Message::ClientJoined((id, name)) => {
+                w.u8(4);
+                w.u16(*id);
+                w.string(name);
+            }
+            Message::PlayerLeft(id) => {
+                w.u8(5);
+                w.u16(*id);
+            }
+            Message::PlayerMoved(id, (x, y), dir, seq) => {
+                w.u8(6);
+                w.u16(*id);
+                w.u16(*x);
+                w.u16(*y);
+                w.direction(dir);
+                w.u32(*seq);
+            }
+            Message::PlayerShot((shooter, target)) => {
+                w.u8(7);
+                w.u16(*shooter);
+                w.u16(*target);
+            }
+            Message::ShotRejected((shooter, target)) => {
+                w.u8(8);
+                w.u16(*shooter);
+                w.u16(*target);
+            }
+            Message::Map(maze) => {
+                w.u8(9);
+                w.u16(maze.len() as u16);
+                w.u16(maze.first().map_or(0, |row| row.len()) as u16);
+                for tile in maze.iter().flatten() {
+                    w.u8(*tile as u8);
+                }
+            }
+            Message::MapChunk(chunk) => {
+                w.u8(10);
+                w.u16(chunk.index as u16);
+                w.u16(chunk.total as u16);
+                w.u16(chunk.width as u16);
+                w.u16(chunk.height as u16);
+                w.u32(chunk.checksum);
+                w.u16(chunk.cells.len() as u16);
+                for tile in chunk.cells.iter() {
+                    w.u8(*tile as u8);
+                }
+            }
+            Message::MapRequest => w.u8(11),
+            Message::ConnectionLost => w.u8(12),
+            Message::Ping => w.u8(13),
+            Message::Pong => w.u8(14),
+            Message::MatchOver(winner) => {
+                w.u8(15);
+                w.string(winner);
+            }
+            Message::DiscoveryRequest => w.u8(16),
+            Message::DiscoveryResponse(info) => {
+                w.u8(17);
+                w.string(&info.name);
+                w.string(&info.map);
+                w.u16(info.players as u16);
+                w.u16(info.max_players as u16);
+                w.u16(info.port);
+                w.u8(info.locked as u8);
+            }
+            Message::Resume(version, token) => {
+                w.u8(18);
+                w.u16(*version);
+                w.u64(*token);
+            }
+            Message::Leave => w.u8(19),
+            Message::ServerClosed => w.u8(20),
+            Message::HostMigration(state) => {
+                w.u8(21);
+                state.encode(w);
+            }
+            Message::HostReady(port) => {
+                w.u8(22);
+                w.u16(*port);
+            }
+            Message::ServerMoved(address) => {
+                w.u8(23);
+                w.string(&address.to_string());
+            }
+            Message::Snapshot(snapshot) => {
+                w.u8(24);
+                snapshot.encode(w);
+            }
+            Message::SnapshotAck(tick) => {
+                w.u8(25);
+                w.u32(*tick);
+            }
+            Message::Respawn((x, y)) => {
+                w.u8(26);
+                w.u16(*x);
+                w.u16(*y);
+            }
+            Message::PasswordChallenge(nonce) => {
+                w.u8(27);
+                w.u64(*nonce);
+            }
+            Message::Chat(id, text) => {
+                w.u8(28);
+                w.u16(*id);
+                w.string(text);
+            }
+            Message::ServerNotice(text) => {
+                w.u8(29);
+                w.string(text);
+            }
+            Message::StatusRequest => w.u8(30),
+            Message::StatusResponse(status) => {
+                w.u8(31);
+                status.encode(w);
+            }
+        }
+    }
+    pub fn decode(r: &mut Reader) -> Result<Message, DecodeError> {
+        let message = match r.u8()? {
+            0 => {
+                let version = r.u16()?;
+                if version != PROTOCOL_VERSION {
+                    // the rest can not be read, the server only needs the version to reject it
+                    r.skip_rest();
+                    return Ok(Message::Join(version, String::new(), 0, false));
+                }
+                Message::Join(version, r.string()?, r.u64()?, r.u8()? != 0)
+            }
+            1 => Message::JoinRejected(r.string()?),
+            2 => Message::Welcome((r.u16()?, r.u64()?)),
+            3 => {
+                let len = r.u16()?;
+                let mut list = vec![];
+                for _ in 0..len {
+                    list.push((r.u16()?, r.string()?));
+                }
+                Message::OpponentList(list)
+            }
+            4 => Message::ClientJoined((r.u16()?, r.string()?)),
+            5 => Message::PlayerLeft(r.u16()?),
+            6 => Message::PlayerMoved(r.u16()?, (r.u16()?, r.u16()?), r.direction()?, r.u32()?),
+            7 => Message::PlayerShot((r.u16()?, r.u16()?)),
+            8 => Message::ShotRejected((r.u16()?, r.u16()?)),
+            9 => {
+                let height = r.u16()? as usize;
+                let width = r.u16()? as usize;
+                // one byte per tile, a size the datagram can not hold is not allocated
+                if width * height > r.remaining() {
+                    return Err(DecodeError::UnexpectedEnd);
+                }
+                let mut maze = vec![vec![0; width]; height];
+                for tile in maze.iter_mut().flatten() {
+                    *tile = r.u8()? as i32;
+                }
+                Message::Map(maze)
+            }
+            10 => {
+                let index = r.u16()? as usize;
+                let total = r.u16()? as usize;
+                let width = r.u16()? as usize;
+                let height = r.u16()? as usize;
+                let checksum = r.u32()?;
+                let len = r.u16()?;
+                let mut cells = vec![];
+                for _ in 0..len {
+                    cells.push(r.u8()? as i32);
+                }
+                Message::MapChunk(MapChunk {
+                    index,
+                    total,
+                    width,
+                    height,
+                    checksum,
+                    cells,
+                })
+            }
+            11 => Message::MapRequest,
+            12 => Message::ConnectionLost,
+            13 => Message::Ping,
+            14 => Message::Pong,
+            15 => Message::MatchOver(r.string()?),
+            16 => Message::DiscoveryRequest,
+            17 => Message::DiscoveryResponse(ServerInfo {
+                name: r.string()?,
+                map: r.string()?,
+                players: r.u16()? as usize,
+                max_players: r.u16()? as usize,
+                port: r.u16()?,
+                locked: r.u8()? != 0,
+            }),
+            18 => {
+                let version = r.u16()?;
+                if version != PROTOCOL_VERSION {
+                    r.skip_rest();
+                    return Ok(Message::Resume(version, 0));
+                }
+                Message::Resume(version, r.u64()?)
+            }
+            19 => Message::Leave,
+            20 => Message::ServerClosed,
+            21 => Message::HostMigration(HostState::decode(r)?),
+            22 => Message::HostReady(r.u16()?),
+            23 => Message::ServerMoved(
+                r.string()?
+                    .parse()
+                    .map_err(|_| DecodeError::InvalidString)?,
+            ),
+            24 => Message::Snapshot(Snapshot::decode(r)?),
+            25 => Message::SnapshotAck(r.u32()?),
+            26 => Message::Respawn((r.u16()?, r.u16()?)),
+            27 => Message::PasswordChallenge(r.u64()?),
+            28 => Message::Chat(r.u16()?, r.string()?),
+            29 => Message::ServerNotice(r.string()?),
+            30 => Message::StatusRequest,
+            31 => Message::StatusResponse(ServerStatus::decode(r)?),
+            tag => return Err(DecodeError::UnknownTag(tag)),
+        };
+        Ok(message)
+    }
+}
+
+impl HostState {
+    fn encode(&self, w: &mut Writer) {
+        let config = &self.config;
+        w.string(&config.name);
+        w.string(&config.map_name);
+        w.u16(config.max_players as u16);
+        match config.score_limit {
+            Some(limit) => {
+                w.u8(1);
+                w.i32(limit);
+            }
+            None => w.u8(0),
+        }
+        // only the key, a host that takes over checks the answers with it
+        match config.password {
+            Some(key) => {
+                w.u8(1);
+                w.u64(key.0);
+                w.u64(key.1);
+            }
+            None => w.u8(0),
+        }
+        w.u16(config.banned.len() as u16);
+        for ip in config.banned.iter() {
+            w.string(&ip.to_string());
+        }
+        w.u16(config.bots as u16);
+        w.u32(config.bot_skill.reaction_time.as_millis() as u32);
+        w.u8((config.bot_skill.accuracy * 100.).round() as u8);
+        w.u16(self.next_id);
+        w.u16(self.players.len() as u16);
+        for player in self.players.iter() {
+            w.u16(player.id);
+            w.string(&player.name);
+            w.i32(player.score);
+            w.u16(player.pos.0);
+            w.u16(player.pos.1);
+            w.direction(&player.dir);
+            w.u64(player.token);
+        }
+        w.u16(self.spectators.len() as u16);
+        for (id, name, token) in self.spectators.iter() {
+            w.u16(*id);
+            w.string(name);
+            w.u64(*token);
+        }
+    }
+    fn decode(r: &mut Reader) -> Result<HostState, DecodeError> {
+        let name = r.string()?;
+        let map_name = r.string()?;
+        let max_players = r.u16()? as usize;
+        let score_limit = match r.u8()? {
+            0 => None,
+            _ => Some(r.i32()?),
+        };
+        let password = match r.u8()? {
+            0 => None,
+            _ => Some((r.u64()?, r.u64()?)),
+        };
+        let mut banned = HashSet::new();
+        for _ in 0..r.u16()? {
+            banned.insert(r.string()?.parse().map_err(|_| DecodeError::InvalidString)?);
+        }
+        let bots = r.u16()? as usize;
+        let bot_skill = BotSkill {
+            reaction_time: Duration::from_millis(r.u32()? as u64),
+            accuracy: r.u8()? as f32 / 100.,
+        };
+        let next_id = r.u16()?;
+        let mut players = vec![];
+        for _ in 0..r.u16()? {
+            players.push(MigratedPlayer {
+                id: r.u16()?,
+                name: r.string()?,
+                score: r.i32()?,
+                pos: (r.u16()?, r.u16()?),
+                dir: r.direction()?,
+                token: r.u64()?,
+            });
+        }
+        let mut spectators = vec![];
+        for _ in 0..r.u16()? {
+            spectators.push((r.u16()?, r.string()?, r.u64()?));
+        }
+        Ok(HostState {
+            config: ServerConfig {
+                name,
+                map_name,
+                max_players,
+                score_limit,
+                password,
+                banned,
+                bots,
+                bot_skill,
+                ..ServerConfig::default()
+            },
+            next_id,
+            players,
+            spectators,
+        })
+    }
+}
+
+impl ServerStatus {
+    fn encode(&self, w: &mut Writer) {
+        w.string(&self.name);
+        w.string(&self.map);
+        w.u16(self.width);
+        w.u16(self.height);
+        w.u16(self.protocol_version);
+        w.u64(self.uptime_secs);
+        w.u16(self.max_players as u16);
+        w.u16(self.spectators as u16);
+        w.u8(self.locked as u8);
+        w.u16(self.players.len() as u16);
+        for player in self.players.iter() {
+            w.string(&player.name);
+            w.i32(player.score);
+            match player.ping_ms {
+                Some(ping) => {
+                    w.u8(1);
+                    w.u32(ping);
+                }
+                None => w.u8(0),
+            }
+        }
+    }
+    fn decode(r: &mut Reader) -> Result<ServerStatus, DecodeError> {
+        let name = r.string()?;
+        let map = r.string()?;
+        let width = r.u16()?;
+        let height = r.u16()?;
+        let protocol_version = r.u16()?;
+        let uptime_secs = r.u64()?;
+        let max_players = r.u16()? as usize;
+        let spectators = r.u16()? as usize;
+        let locked = r.u8()? != 0;
+        let mut players = vec![];
+        for _ in 0..r.u16()? {
+            players.push(PlayerStatus {
+                name: r.string()?,
+                score: r.i32()?,
+                ping_ms: match r.u8()? {
+                    0 => None,
+                    _ => Some(r.u32()?),
+                },
+            });
+        }
+        Ok(ServerStatus {
+            name,
+            map,
+            width,
+            height,
+            protocol_version,
+            uptime_secs,
+            max_players,
+            spectators,
+            locked,
+            players,
+        })
+    }
+}
+
+// each changed player starts with a byte telling which fields follow
+const SNAPSHOT_POS: u8 = 1;
+const SNAPSHOT_DIR: u8 = 2;
+const SNAPSHOT_SCORE: u8 = 4;
+
+impl Snapshot {
+    fn encode(&self, w: &mut Writer) {
+        w.u32(self.tick);
+        w.u32(self.baseline);
+        w.u32(self.last_move);
+        w.u16(self.changed.len() as u16);
+        for (id, delta) in self.changed.iter() {
+            w.u16(*id);
+            let mut fields = 0;
+            if delta.pos.is_some() {
+                fields |= SNAPSHOT_POS;
+            }
+            if delta.dir.is_some() {
+                fields |= SNAPSHOT_DIR;
+            }
+            if delta.score.is_some() {
+                fields |= SNAPSHOT_SCORE;
+            }
+            w.u8(fields);
+            if let Some((x, y)) = delta.pos {
+                w.u16(x);
+                w.u16(y);
+            }
+            if let Some(dir) = &delta.dir {
+                w.direction(dir);
+            }
+            if let Some(score) = delta.score {
+                w.i32(score);
+            }
+        }
+        w.u16(self.removed.len() as u16);
+        for id in self.removed.iter() {
+            w.u16(*id);
+        }
+    }
+    fn decode(r: &mut Reader) -> Result<Snapshot, DecodeError> {
+        let tick = r.u32()?;
+        let baseline = r.u32()?;
+        let last_move = r.u32()?;
+        let mut changed = vec![];
+        for _ in 0..r.u16()? {
+            let id = r.u16()?;
+            let fields = r.u8()?;
+            let mut delta = PlayerDelta::default();
+            if fields & SNAPSHOT_POS != 0 {
+                delta.pos = Some((r.u16()?, r.u16()?));
+            }
+            if fields & SNAPSHOT_DIR != 0 {
+                delta.dir = Some(r.direction()?);
+            }
+            if fields & SNAPSHOT_SCORE != 0 {
+                delta.score = Some(r.i32()?);
+            }
+            changed.push((id, delta));
+        }
+        let mut removed = vec![];
+        for _ in 0..r.u16()? {
+            removed.push(r.u16()?);
+        }
+        Ok(Snapshot {
+            tick,
+            baseline,
+            last_move,
+            changed,
+            removed,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn host_state() -> HostState {
+        HostState {
+            config: ServerConfig {
+                name: "Test".to_string(),
+                map_name: "maps/1.txt".to_string(),
+                max_players: 6,
+                score_limit: Some(10),
+                password: Some((1, 2)),
+                banned: HashSet::from(["10.0.0.9".parse().unwrap()]),
+                bots: 2,
+                bot_skill: BotSkill {
+                    reaction_time: Duration::from_millis(300),
+                    accuracy: 0.5,
+                },
+                ..ServerConfig::default()
+            },
+            next_id: 4,
+            players: vec![MigratedPlayer {
+                id: 1,
+                name: "alice".to_string(),
+                score: -1,
+                pos: (3, 4),
+                dir: Direction::Down,
+                token: u64::MAX,
+            }],
+            spectators: vec![(3, "carol".to_string(), 7)],
+        }
+    }
+
+    fn snapshot() -> Snapshot {
+        let moved = PlayerDelta {
+            pos: Some((1, 2)),
+            dir: None,
+            score: Some(3),
+        };
+        let turned = PlayerDelta {
+            dir: Some(Direction::Right),
+            ..PlayerDelta::default()
+        };
+        Snapshot {
+            tick: 12,
+            baseline: 10,
+            last_move: 5,
+            changed: vec![(1, moved), (2, turned)],
+            removed: vec![3],
+        }
+    }
+
+    fn status() -> ServerStatus {
+        ServerStatus {
+            name: "Test".to_string(),
+            map: "maps/1.txt".to_string(),
+            width: 20,
+            height: 10,
+            protocol_version: PROTOCOL_VERSION,
+            uptime_secs: 99,
+            max_players: 8,
+            spectators: 1,
+            locked: true,
+            players: vec![
+                PlayerStatus {
+                    name: "alice".to_string(),
+                    score: 2,
+                    ping_ms: Some(30),
+                },
+                PlayerStatus {
+                    name: "bob".to_string(),
+                    score: 0,
+                    ping_ms: None,
+                },
+            ],
+        }
+    }
+
+    // one of every message, in the order of their tags
+    fn messages() -> Vec<Message> {
+        vec![
+            Message::Join(PROTOCOL_VERSION, "alice".to_string(), 42, true),
+            Message::JoinRejected("full".to_string()),
+            Message::Welcome((1, 99)),
+            Message::OpponentList(vec![(1, "alice".to_string()), (2, "bob".to_string())]),
+            Message::ClientJoined((2, "bob".to_string())),
+            Message::PlayerLeft(2),
+            Message::PlayerMoved(1, (3, 4), Direction::Left, 8),
+            Message::PlayerShot((1, 2)),
+            Message::ShotRejected((2, 1)),
+            Message::Map(vec![vec![1, 1, 1], vec![1, 0, 1]]),
+            Message::MapChunk(MapChunk {
+                index: 1,
+                total: 2,
+                width: 3,
+                height: 2,
+                checksum: 0xdead_beef,
+                cells: vec![1, 0, 1],
+            }),
+            Message::MapRequest,
+            Message::ConnectionLost,
+            Message::Ping,
+            Message::Pong,
+            Message::MatchOver("alice".to_string()),
+            Message::DiscoveryRequest,
+            Message::DiscoveryResponse(ServerInfo {
+                name: "Test".to_string(),
+                map: "maps/1.txt".to_string(),
+                players: 2,
+                max_players: 8,
+                port: 35353,
+                locked: false,
+            }),
+            Message::Resume(PROTOCOL_VERSION, 99),
+            Message::Leave,
+            Message::ServerClosed,
+            Message::HostMigration(host_state()),
+            Message::HostReady(35354),
+            Message::ServerMoved("[::1]:35354".parse().unwrap()),
+            Message::Snapshot(snapshot()),
+            Message::SnapshotAck(12),
+            Message::Respawn((1, 1)),
+            Message::PasswordChallenge(7),
+            Message::Chat(1, "hi".to_string()),
+            Message::ServerNotice("Banned 10.0.0.9".to_string()),
+            Message::StatusRequest,
+            Message::StatusResponse(status()),
+        ]
+    }
+
+    fn decode(bytes: &[u8]) -> Result<Packet, DecodeError> {
+        Packet::decode(bytes)
+    }
+
+    #[test]
+    fn every_message_survives_the_wire() {
+        let messages = messages();
+        let unknown = messages.len() as u8;
+        for (tag, m) in messages.into_iter().enumerate() {
+            let bytes = Packet::Reliable(tag as u32, m.clone()).to_bytes();
+            assert_eq!(bytes[5], tag as u8, "{:?} is not in tag order", m);
+            match decode(&bytes) {
+                Ok(Packet::Reliable(seq, decoded)) => {
+                    assert_eq!(seq, tag as u32);
+                    assert_eq!(format!("{:?}", decoded), format!("{:?}", m));
+                }
+                other => panic!("{:?} came back as {:?}", m, other.map(|_| ())),
+            }
+        }
+        assert!(matches!(decode(&Packet::Ack(3).to_bytes()), Ok(Packet::Ack(3))));
+        // the list stops at the last tag
+        assert!(matches!(decode(&[0, unknown]), Err(DecodeError::UnknownTag(tag)) if tag == unknown));
+    }
+
+    #[test]
+    fn cut_off_datagrams_are_rejected() {
+        for m in messages() {
+            let bytes = Packet::Unreliable(m.clone()).to_bytes();
+            for len in 0..bytes.len() {
+                let decoded = decode(&bytes[..len]);
+                assert!(
+                    matches!(decoded, Err(DecodeError::UnexpectedEnd)),
+                    "{:?} cut to {} bytes",
+                    m,
+                    len
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn trailing_bytes_are_rejected() {
+        for m in messages() {
+            let mut bytes = Packet::Unreliable(m.clone()).to_bytes();
+            bytes.push(0);
+            assert!(
+                matches!(decode(&bytes), Err(DecodeError::TrailingBytes(1))),
+                "{:?} with a byte too many",
+                m
+            );
+        }
+    }
+
+    #[test]
+    fn oversized_map_is_not_allocated() {
+        let mut w = Writer::new();
+        w.u8(0);
+        w.u8(9);
+        w.u16(u16::MAX);
+        w.u16(u16::MAX);
+        w.u8(1);
+        assert!(matches!(decode(&w.finish()), Err(DecodeError::UnexpectedEnd)));
+    }
+
+    #[test]
+    fn full_server_fits_a_datagram() {
+        use crate::server::{MAX_NAME_LENGTH, MAX_PLAYERS};
+        use crate::snapshot::{PlayerState, World};
+        // the longest names, every letter after the first takes four bytes
+        let name = |n: usize| format!("{}{}", n % 10, "\u{1d11e}".repeat(MAX_NAME_LENGTH - 1));
+        let ids = 1..=MAX_PLAYERS as PlayerId;
+        let world: World = ids
+            .clone()
+            .map(|id| (id, PlayerState { pos: (id, id), dir: Direction::Up, score: i32::MIN }))
+            .collect();
+        let mut state = host_state();
+        state.players = ids
+            .clone()
+            .map(|id| MigratedPlayer {
+                id,
+                name: name(id as usize),
+                score: i32::MIN,
+                pos: (id, id),
+                dir: Direction::Up,
+                token: u64::MAX,
+            })
+            .collect();
+        let mut status = status();
+        status.players = ids
+            .clone()
+            .map(|id| PlayerStatus {
+                name: name(id as usize),
+                score: i32::MIN,
+                ping_ms: Some(u32::MAX),
+            })
+            .collect();
+        let largest = [
+            Message::OpponentList(ids.map(|id| (id, name(id as usize))).collect()),
+            Message::Snapshot(Snapshot::full(u32::MAX, &world)),
+            Message::StatusResponse(status),
+            Message::HostMigration(state),
+        ];
+        for m in largest {
+            let bytes = Packet::Reliable(u32::MAX, m.clone()).to_bytes();
+            assert!(bytes.len() <= 2048, "{} bytes for {:?}", bytes.len(), m);
+        }
+    }
+}
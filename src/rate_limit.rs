@@ -0,0 +1,116 @@
+use std::{
+    collections::HashMap,
+    net::{IpAddr, SocketAddr},
+    time::{Duration, Instant},
+};
+
+// how much traffic one host may send before the server drops it
+#[derive(Debug, Clone)]
+pub struct RateLimit {
+    pub packets_per_second: f32, // tokens added per second
+    pub burst: f32,              // most tokens a peer can save up
+    pub ban_after: u32,          // dropped packets in a row before the address is banned
+    pub ban_duration: Duration,
+}
+
+impl Default for RateLimit {
+    fn default() -> Self {
+        // a playing client sends about 40 packets per second, two fit behind one address
+        RateLimit {
+            packets_per_second: 100.,
+            burst: 200.,
+            ban_after: 500,
+            ban_duration: Duration::from_secs(60),
+        }
+    }
+}
+
+struct Bucket {
+    tokens: f32,
+    refilled: Instant,
+    strikes: u32, // packets dropped since the bucket last had tokens
+}
+
+// Token bucket per host, all ports of an address share one so a flood can not be
+// spread over many source ports. Abusive hosts are banned for a while.
+#[derive(Default)]
+pub struct RateLimiter {
+    pub config: RateLimit,
+    buckets: HashMap<IpAddr, Bucket>,
+    banned_until: HashMap<IpAddr, Instant>,
+    pub dropped: HashMap<IpAddr, u64>, // packets dropped per host, forgotten with its bucket
+    dropped_total: u64,
+}
+
+impl RateLimiter {
+    pub fn new(config: RateLimit) -> RateLimiter {
+        RateLimiter {
+            config,
+            ..RateLimiter::default()
+        }
+    }
+    // takes `cost` tokens from the peers bucket, false when the packet has to be dropped
+    pub fn allow(&mut self, peer: SocketAddr, cost: f32) -> bool {
+        let ip = peer.ip();
+        if self.is_banned(&ip) {
+            self.drop_packet(ip);
+            return false;
+        }
+        let config = &self.config;
+        let bucket = self.buckets.entry(ip).or_insert(Bucket {
+            tokens: config.burst,
+            refilled: Instant::now(),
+            strikes: 0,
+        });
+        let refill = bucket.refilled.elapsed().as_secs_f32() * config.packets_per_second;
+        bucket.tokens = (bucket.tokens + refill).min(config.burst);
+        bucket.refilled = Instant::now();
+        if bucket.tokens >= cost {
+            bucket.tokens -= cost;
+            bucket.strikes = 0;
+            return true;
+        }
+        bucket.strikes += 1;
+        let ban = bucket.strikes >= config.ban_after;
+        self.drop_packet(ip);
+        if ban {
+            println!(
+                "Banned {} for {:?}, it keeps flooding the server",
+                ip, self.config.ban_duration
+            );
+            let until = Instant::now() + self.config.ban_duration;
+            self.banned_until.insert(ip, until);
+            self.buckets.remove(&ip);
+        }
+        false
+    }
+    fn drop_packet(&mut self, ip: IpAddr) {
+        *self.dropped.entry(ip).or_insert(0) += 1;
+        self.dropped_total += 1;
+    }
+    pub fn is_banned(&self, ip: &IpAddr) -> bool {
+        self.banned_until
+            .get(ip)
+            .is_some_and(|until| Instant::now() < *until)
+    }
+    // drops the state of peers that went quiet and of bans that ran out
+    pub fn cleanup(&mut self) {
+        let burst_time = Duration::from_secs_f32(self.config.burst / self.config.packets_per_second);
+        self.buckets
+            .retain(|_, bucket| bucket.refilled.elapsed() < burst_time);
+        self.banned_until.retain(|_, until| Instant::now() < *until);
+        // the total keeps counting them
+        self.dropped
+            .retain(|ip, _| self.buckets.contains_key(ip) || self.banned_until.contains_key(ip));
+    }
+    pub fn dropped_total(&self) -> u64 {
+        self.dropped_total
+    }
+    pub fn banned(&self) -> Vec<IpAddr> {
+        self.banned_until
+            .iter()
+            .filter(|(_, until)| Instant::now() < **until)
+            .map(|(ip, _)| *ip)
+            .collect()
+    }
+}
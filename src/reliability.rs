@@ -0,0 +1,241 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    net::SocketAddr,
+    time::{Duration, Instant},
+};
+
+use crate::server::Message;
+
+pub const RESEND_TIMEOUT: Duration = Duration::from_millis(200);
+pub const MAX_RESENDS: u32 = 15;
+// reliable messages a peer may send ahead of the one we wait for
+pub const RECEIVE_WINDOW: u32 = 256;
+
+// What actually goes over the wire. Reliable messages carry a sequence number
+// and are resent until the other side answers with an Ack for it.
+// The binary encoding lives in protocol.rs.
+#[derive(Debug)]
+pub enum Packet {
+    Unreliable(Message),
+    Reliable(u32, Message), // Sequence number, message
+    Ack(u32),
+}
+
+struct Pending {
+    bytes: Vec<u8>,
+    sent_at: Instant,
+    resends: u32,
+}
+
+// sequence numbers of one peer in both directions
+#[derive(Default)]
+struct Channel {
+    next_seq: u32,
+    expected_seq: u32,
+    pending: BTreeMap<u32, Pending>,      // sent and not acked yet
+    out_of_order: BTreeMap<u32, Message>, // received before the messages in front of them
+}
+
+#[derive(Default)]
+pub struct Reliability {
+    channels: HashMap<SocketAddr, Channel>,
+    failed: Vec<SocketAddr>, // peers that stopped acking, their channel is gone
+}
+
+impl Reliability {
+    pub fn new() -> Reliability {
+        Reliability::default()
+    }
+    // encodes the message for the peer, reliable messages are kept until acked
+    pub fn prepare(&mut self, peer: SocketAddr, msg: Message) -> Vec<u8> {
+        if !msg.is_reliable() {
+            return Packet::Unreliable(msg).to_bytes();
+        }
+        let channel = self.channels.entry(peer).or_default();
+        let seq = channel.next_seq;
+        channel.next_seq += 1;
+        let bytes = Packet::Reliable(seq, msg).to_bytes();
+        channel.pending.insert(
+            seq,
+            Pending {
+                bytes: bytes.clone(),
+                sent_at: Instant::now(),
+                resends: 0,
+            },
+        );
+        bytes
+    }
+    // returns the messages that can be handled now, in order, and the ack to send back
+    pub fn receive(&mut self, peer: SocketAddr, packet: Packet) -> (Vec<Message>, Option<Vec<u8>>) {
+        match packet {
+            Packet::Unreliable(msg) => (vec![msg], None),
+            Packet::Ack(seq) => {
+                if let Some(channel) = self.channels.get_mut(&peer) {
+                    channel.pending.remove(&seq);
+                }
+                (vec![], None)
+            }
+            Packet::Reliable(seq, msg) => {
+                let ack = Some(Packet::Ack(seq).to_bytes());
+                let channel = self.channels.entry(peer).or_default();
+                if seq < channel.expected_seq {
+                    // duplicate, our ack got lost
+                    return (vec![], ack);
+                }
+                // too far ahead to keep, the peer resends it later
+                if seq - channel.expected_seq >= RECEIVE_WINDOW {
+                    return (vec![], None);
+                }
+                channel.out_of_order.insert(seq, msg);
+                let mut ready = vec![];
+                while let Some(msg) = channel.out_of_order.remove(&channel.expected_seq) {
+                    ready.push(msg);
+                    channel.expected_seq += 1;
+                }
+                (ready, ack)
+            }
+        }
+    }
+    // packets whose ack did not arrive in time. A message that is still not acked after
+    // MAX_RESENDS would hold up everything behind it forever, the whole channel is
+    // dropped then and the peer shows up in failed_peers.
+    pub fn resends(&mut self) -> Vec<(SocketAddr, Vec<u8>)> {
+        let mut resends = vec![];
+        let mut failed = vec![];
+        for (peer, channel) in self.channels.iter_mut() {
+            for (seq, pending) in channel.pending.iter_mut() {
+                if pending.sent_at.elapsed() < RESEND_TIMEOUT {
+                    continue;
+                }
+                if pending.resends >= MAX_RESENDS {
+                    println!("Giving up on message {} to {}", seq, peer);
+                    failed.push(*peer);
+                    break;
+                }
+                pending.resends += 1;
+                pending.sent_at = Instant::now();
+                resends.push((*peer, pending.bytes.clone()));
+            }
+        }
+        resends.retain(|(peer, _)| !failed.contains(peer));
+        for peer in failed {
+            self.channels.remove(&peer);
+            self.failed.push(peer);
+        }
+        resends
+    }
+    // peers whose channel was dropped since the last call, both ends have to start over
+    pub fn failed_peers(&mut self) -> Vec<SocketAddr> {
+        std::mem::take(&mut self.failed)
+    }
+    // true while a reliable message still waits for its ack
+    pub fn has_pending(&self) -> bool {
+        self.channels.values().any(|channel| !channel.pending.is_empty())
+    }
+    pub fn forget(&mut self, peer: &SocketAddr) {
+        self.channels.remove(peer);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn peer() -> SocketAddr {
+        "10.0.0.2:35353".parse().unwrap()
+    }
+
+    fn chat(text: &str) -> Message {
+        Message::Chat(1, text.to_string())
+    }
+
+    // the chat lines the receiver handed on, in order
+    fn texts(messages: Vec<Message>) -> Vec<String> {
+        messages
+            .into_iter()
+            .map(|m| match m {
+                Message::Chat(_, text) => text,
+                m => panic!("unexpected {:?}", m),
+            })
+            .collect()
+    }
+
+    fn sent(sender: &mut Reliability, text: &str) -> Packet {
+        Packet::from_bytes(&sender.prepare(peer(), chat(text))).unwrap()
+    }
+
+    // pretends the last send was a resend timeout ago
+    fn age(reliability: &mut Reliability) {
+        for channel in reliability.channels.values_mut() {
+            for pending in channel.pending.values_mut() {
+                pending.sent_at = Instant::now() - RESEND_TIMEOUT;
+            }
+        }
+    }
+
+    #[test]
+    fn messages_are_handed_on_in_order() {
+        let (mut sender, mut receiver) = (Reliability::new(), Reliability::new());
+        let packets: Vec<Packet> = ["a", "b", "c"].iter().map(|t| sent(&mut sender, t)).collect();
+        let mut packets = packets.into_iter();
+        let (a, b, c) = (packets.next().unwrap(), packets.next().unwrap(), packets.next().unwrap());
+
+        let (ready, ack) = receiver.receive(peer(), c);
+        assert!(ready.is_empty());
+        assert!(ack.is_some());
+        assert_eq!(texts(receiver.receive(peer(), a).0), ["a"]);
+        assert_eq!(texts(receiver.receive(peer(), b).0), ["b", "c"]);
+    }
+
+    #[test]
+    fn duplicate_is_acked_again_but_not_handed_on() {
+        let (mut sender, mut receiver) = (Reliability::new(), Reliability::new());
+        let bytes = sender.prepare(peer(), chat("a"));
+        let (ready, _) = receiver.receive(peer(), Packet::from_bytes(&bytes).unwrap());
+        assert_eq!(texts(ready), ["a"]);
+
+        // the ack got lost, the sender resends
+        age(&mut sender);
+        let resent = sender.resends();
+        assert_eq!(resent.len(), 1);
+        let (ready, ack) = receiver.receive(peer(), Packet::from_bytes(&resent[0].1).unwrap());
+        assert!(ready.is_empty());
+        let ack = ack.expect("the duplicate was not acked");
+
+        sender.receive(peer(), Packet::from_bytes(&ack).unwrap());
+        assert!(!sender.has_pending());
+    }
+
+    #[test]
+    fn messages_beyond_the_window_are_dropped() {
+        let mut receiver = Reliability::new();
+        let (ready, ack) = receiver.receive(peer(), Packet::Reliable(RECEIVE_WINDOW, chat("far")));
+        assert!(ready.is_empty());
+        assert!(ack.is_none(), "a dropped message must be resent, it can not be acked");
+
+        let (ready, ack) = receiver.receive(peer(), Packet::Reliable(RECEIVE_WINDOW - 1, chat("last")));
+        assert!(ready.is_empty());
+        assert!(ack.is_some());
+        let held = &receiver.channels[&peer()].out_of_order;
+        assert_eq!(held.keys().copied().collect::<Vec<u32>>(), [RECEIVE_WINDOW - 1]);
+    }
+
+    #[test]
+    fn peer_fails_after_max_resends() {
+        let mut sender = Reliability::new();
+        sender.prepare(peer(), chat("a"));
+        // unreliable messages are never kept
+        sender.prepare(peer(), Message::Ping);
+        for _ in 0..MAX_RESENDS {
+            age(&mut sender);
+            assert_eq!(sender.resends().len(), 1);
+            assert!(sender.failed_peers().is_empty());
+        }
+        age(&mut sender);
+        assert!(sender.resends().is_empty());
+        assert_eq!(sender.failed_peers(), [peer()]);
+        assert!(!sender.has_pending());
+        // reported once
+        assert!(sender.failed_peers().is_empty());
+    }
+}
@@ -0,0 +1,347 @@
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+#[cfg(feature = "gui")]
+use ggez::graphics::{self, Color, DrawMode, DrawParam, PxScale, Rect, Text, TextFragment};
+#[cfg(feature = "gui")]
+use ggez::{Context, GameResult};
+
+use crate::map_transfer::MapAssembly;
+use crate::protocol::{DecodeError, Reader, Writer, PROTOCOL_VERSION};
+use crate::server::Message;
+use crate::snapshot::{Snapshot, SnapshotHistory, World};
+#[cfg(feature = "gui")]
+use crate::{SCREEN_HEIGHT, SCREEN_WIDTH};
+
+// A replay file starts with REPLAY_MAGIC, the protocol version and who recorded it.
+// Every entry follows with its length: milliseconds since the recording started,
+// 1 when the message was sent and 0 when it was received, the peer and the message
+// encoded like on the wire.
+pub const REPLAY_MAGIC: &[u8] = b"MAZEREPLAY";
+pub const REPLAY_DIR: &str = "replays";
+// how far the arrow keys jump in a replay
+pub const SEEK_STEP: Duration = Duration::from_secs(5);
+// the recording is written out at least this often, a killed process loses less
+const FLUSH_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Origin {
+    Client,
+    Server,
+}
+
+// Writes every message that is sent or received to a replay file
+pub struct Recorder {
+    file: BufWriter<File>,
+    started: Instant,
+    last_flush: Instant,
+}
+
+impl Recorder {
+    pub fn create(path: &Path, origin: Origin) -> io::Result<Recorder> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let mut file = BufWriter::new(File::create(path)?);
+        file.write_all(REPLAY_MAGIC)?;
+        file.write_all(&PROTOCOL_VERSION.to_le_bytes())?;
+        file.write_all(&[(origin == Origin::Server) as u8])?;
+        Ok(Recorder {
+            file,
+            started: Instant::now(),
+            last_flush: Instant::now(),
+        })
+    }
+    // a new file in REPLAY_DIR named after the player and the time
+    pub fn create_new(name: &str, origin: Origin) -> io::Result<(Recorder, String)> {
+        let secs = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |time| time.as_secs());
+        let path = format!("{}/{}-{}.replay", REPLAY_DIR, name, secs);
+        Ok((Recorder::create(Path::new(&path), origin)?, path))
+    }
+    pub fn record(&mut self, sent: bool, peer: &str, message: &Message) {
+        let mut w = Writer::new();
+        w.u64(self.started.elapsed().as_millis() as u64);
+        w.u8(sent as u8);
+        w.string(peer);
+        message.encode(&mut w);
+        let entry = w.finish();
+        let written = self
+            .file
+            .write_all(&(entry.len() as u32).to_le_bytes())
+            .and_then(|_| self.file.write_all(&entry));
+        if let Err(e) = written {
+            println!("Cant record message: {}", e);
+        }
+        if self.last_flush.elapsed() >= FLUSH_INTERVAL {
+            self.last_flush = Instant::now();
+            let _ = self.file.flush();
+        }
+    }
+}
+
+// one recorded message
+pub struct Entry {
+    pub at: Duration,
+    pub sent: bool,
+    pub peer: String,
+    pub message: Message,
+}
+
+pub fn read_recording(path: &Path) -> io::Result<(Origin, Vec<Entry>)> {
+    let bytes = fs::read(path)?;
+    let invalid = |reason: String| io::Error::new(io::ErrorKind::InvalidData, reason);
+    let header = REPLAY_MAGIC.len() + 3;
+    if bytes.len() < header || !bytes.starts_with(REPLAY_MAGIC) {
+        return Err(invalid("not a replay file".to_string()));
+    }
+    let version = u16::from_le_bytes([bytes[header - 3], bytes[header - 2]]);
+    if version != PROTOCOL_VERSION {
+        return Err(invalid(format!("recorded with protocol {}", version)));
+    }
+    let origin = match bytes[header - 1] {
+        0 => Origin::Client,
+        _ => Origin::Server,
+    };
+    let mut entries = vec![];
+    let mut rest = &bytes[header..];
+    // a recording that was cut off ends with a partial entry, everything before it is kept
+    while rest.len() >= 4 {
+        let len = u32::from_le_bytes([rest[0], rest[1], rest[2], rest[3]]) as usize;
+        if rest.len() < 4 + len {
+            break;
+        }
+        let entry = read_entry(&mut Reader::new(&rest[4..4 + len]))
+            .map_err(|e| invalid(e.to_string()))?;
+        entries.push(entry);
+        rest = &rest[4 + len..];
+    }
+    Ok((origin, entries))
+}
+
+fn read_entry(r: &mut Reader) -> Result<Entry, DecodeError> {
+    Ok(Entry {
+        at: Duration::from_millis(r.u64()?),
+        sent: r.u8()? != 0,
+        peer: r.string()?,
+        message: Message::decode(r)?,
+    })
+}
+
+// what the game shows of a match, the rest needs a connection or is for the server
+fn is_replayed(message: &Message) -> bool {
+    matches!(
+        message,
+        Message::OpponentList(_)
+            | Message::ClientJoined(_)
+            | Message::PlayerLeft(_)
+            | Message::PlayerShot(_)
+            | Message::Map(_)
+            | Message::Snapshot(_)
+            | Message::MatchOver(_)
+            | Message::Chat(..)
+            | Message::ServerNotice(_)
+    )
+}
+
+// A client recorded what its game saw. A server sent every message to each client,
+// with snapshots relative to what each acked and the map in chunks, it is turned into
+// the stream one client would have seen.
+fn server_view(entries: Vec<Entry>) -> Vec<(Duration, Message)> {
+    let mut worlds = SnapshotHistory::default();
+    let mut maps: HashMap<String, MapAssembly> = HashMap::new();
+    let mut last_map: Option<Vec<Vec<i32>>> = None;
+    let mut last_sent: Option<(Duration, Vec<u8>)> = None;
+    let mut messages = vec![];
+    for entry in entries.into_iter().filter(|entry| entry.sent) {
+        let message = match entry.message {
+            // every client gets the same world at a tick
+            Message::Snapshot(snapshot) => {
+                if snapshot.tick <= worlds.latest_tick() {
+                    continue;
+                }
+                let baseline = match snapshot.baseline {
+                    0 => World::new(),
+                    tick => match worlds.get(tick) {
+                        Some(world) => world.clone(),
+                        None => continue,
+                    },
+                };
+                let world = match snapshot.apply(&baseline) {
+                    Some(world) => world,
+                    None => continue,
+                };
+                let full = Snapshot::full(snapshot.tick, &world);
+                worlds.push(snapshot.tick, world);
+                Message::Snapshot(full)
+            }
+            Message::MapChunk(chunk) => {
+                let assembly = maps.entry(entry.peer).or_default();
+                match assembly.add(chunk) {
+                    Some(Ok(maze)) if last_map.as_ref() != Some(&maze) => {
+                        last_map = Some(maze.clone());
+                        Message::Map(maze)
+                    }
+                    _ => continue,
+                }
+            }
+            message => message,
+        };
+        // a message to everyone is recorded once per client
+        let mut w = Writer::new();
+        message.encode(&mut w);
+        let bytes = w.finish();
+        let repeated = last_sent.as_ref().is_some_and(|(at, last)| {
+            *last == bytes && entry.at.saturating_sub(*at) < Duration::from_millis(10)
+        });
+        last_sent = Some((entry.at, bytes));
+        if !repeated {
+            messages.push((entry.at, message));
+        }
+    }
+    messages
+}
+
+// A recorded match played back, paused and scrubbed in the game view
+pub struct Replay {
+    pub name: String,
+    messages: Vec<(Duration, Message)>,
+    next: usize, // first message that was not handed out yet
+    time: Duration,
+    pub paused: bool,
+    last_update: Instant,
+}
+
+impl Replay {
+    pub fn load(path: &Path) -> io::Result<Replay> {
+        let (origin, entries) = read_recording(path)?;
+        let messages: Vec<(Duration, Message)> = match origin {
+            Origin::Client => entries
+                .into_iter()
+                .filter(|entry| !entry.sent)
+                .map(|entry| (entry.at, entry.message))
+                .collect(),
+            Origin::Server => server_view(entries),
+        };
+        let messages: Vec<(Duration, Message)> = messages
+            .into_iter()
+            .filter(|(_, message)| is_replayed(message))
+            .collect();
+        if !messages.iter().any(|(_, m)| matches!(m, Message::Map(_))) {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "the recording has no map"));
+        }
+        let name = path
+            .file_stem()
+            .map_or(String::new(), |stem| stem.to_string_lossy().to_string());
+        Ok(Replay {
+            name,
+            messages,
+            next: 0,
+            time: Duration::ZERO,
+            paused: false,
+            last_update: Instant::now(),
+        })
+    }
+    // the map the game starts with
+    pub fn first_map(&self) -> Vec<Vec<i32>> {
+        self.messages
+            .iter()
+            .find_map(|(_, message)| match message {
+                Message::Map(maze) => Some(maze.clone()),
+                _ => None,
+            })
+            .unwrap()
+    }
+    pub fn length(&self) -> Duration {
+        self.messages.last().map_or(Duration::ZERO, |(at, _)| *at)
+    }
+    pub fn toggle_pause(&mut self) {
+        self.paused = !self.paused;
+    }
+    // the messages that are due, the clock only runs while the replay is not paused
+    pub fn advance(&mut self) -> Vec<Message> {
+        if !self.paused {
+            self.time = (self.time + self.last_update.elapsed()).min(self.length());
+        }
+        self.last_update = Instant::now();
+        let mut due = vec![];
+        while self.next < self.messages.len() && self.messages[self.next].0 <= self.time {
+            due.push(self.messages[self.next].1.clone());
+            self.next += 1;
+        }
+        due
+    }
+    // jumps to `to`, true when the replay starts over and the game has to be built again
+    pub fn seek(&mut self, to: Duration) -> bool {
+        let to = to.min(self.length());
+        let back = to < self.time;
+        self.time = to;
+        if back {
+            self.next = 0;
+        }
+        back
+    }
+    pub fn seek_by(&mut self, step: Duration, forward: bool) -> bool {
+        let to = if forward {
+            self.time + step
+        } else {
+            self.time.saturating_sub(step)
+        };
+        self.seek(to)
+    }
+    #[cfg(feature = "gui")]
+    pub fn bar_rect() -> Rect {
+        Rect::new(10., SCREEN_HEIGHT - 18., SCREEN_WIDTH - 20., 8.)
+    }
+    // the point in the replay that was clicked on the progress bar
+    #[cfg(feature = "gui")]
+    pub fn clicked_time(&self, x: f32, y: f32) -> Option<Duration> {
+        let bar = Replay::bar_rect();
+        let area = Rect::new(bar.x, bar.y - 8., bar.w, bar.h + 16.);
+        if !area.contains([x, y]) {
+            return None;
+        }
+        Some(self.length().mul_f32(((x - bar.x) / bar.w).clamp(0., 1.)))
+    }
+    #[cfg(feature = "gui")]
+    pub fn draw(&self, canvas: &mut graphics::Canvas, ctx: &mut Context) -> GameResult {
+        let bar = Replay::bar_rect();
+        let area = Rect::new(0., bar.y - 22., SCREEN_WIDTH, SCREEN_HEIGHT - bar.y + 22.);
+        let background = graphics::Mesh::new_rectangle(ctx, DrawMode::fill(), area, Color::WHITE)?;
+        canvas.draw(&background, DrawParam::default());
+        let outline = graphics::Mesh::new_rectangle(ctx, DrawMode::stroke(1.), bar, Color::BLACK)?;
+        canvas.draw(&outline, DrawParam::default());
+        let length = self.length().as_secs_f32().max(0.001);
+        let done = Rect::new(bar.x, bar.y, bar.w * self.time.as_secs_f32() / length, bar.h);
+        if done.w > 0. {
+            let progress = graphics::Mesh::new_rectangle(ctx, DrawMode::fill(), done, Color::BLACK)?;
+            canvas.draw(&progress, DrawParam::default());
+        }
+        let state = if self.paused { "paused" } else { "playing" };
+        let caption = format!(
+            "{}  {} / {} {}  Space pause, arrows skip, Tab view, Esc leave",
+            self.name,
+            clock(self.time),
+            clock(self.length()),
+            state
+        );
+        let text = Text::new(TextFragment {
+            text: caption,
+            color: Some(Color::BLACK),
+            font: Some("LiberationMono-Regular".into()),
+            scale: Some(PxScale::from(12.)),
+        });
+        canvas.draw(&text, DrawParam::default().dest([bar.x, bar.y - 18.]));
+        Ok(())
+    }
+}
+
+// minutes and seconds
+#[cfg(feature = "gui")]
+fn clock(time: Duration) -> String {
+    format!("{}:{:02}", time.as_secs() / 60, time.as_secs() % 60)
+}
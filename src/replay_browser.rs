@@ -0,0 +1,141 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::PathBuf;
+
+use ggez::{
+    graphics::{self, Color, DrawParam, Rect, Text, TextAlign, TextLayout},
+    Context, GameResult,
+};
+
+use crate::{
+    drawer::Drawer,
+    replay::{Replay, REPLAY_DIR},
+    view::View,
+    SCREEN_WIDTH,
+};
+
+use super::main_menu::MainMenuStruct;
+
+const LIST_Y: f32 = 225.0;
+const ROW_HEIGHT: f32 = 20.0;
+const ROW_WIDTH: f32 = 400.0;
+// rows that fit above the eye picture
+const MAX_ROWS: usize = 20;
+
+pub struct ReplayBrowserStruct {
+    pub element_rects: HashMap<String, Rect>, // holds button rects
+    pub drawer: Drawer,
+    pub error: Option<String>,
+    pub replays: Vec<PathBuf>, // newest first
+    pub replay: Option<Replay>, // the clicked replay, the state starts it
+}
+
+impl ReplayBrowserStruct {
+    pub fn new(ctx: &mut Context) -> GameResult<ReplayBrowserStruct> {
+        let drawer = Drawer::new(ctx)?;
+        let mut elements = HashMap::new();
+        elements.insert(
+            "BACK_ARROW_IMG".to_string(),
+            Rect::new(100.0 - 6.0, 100.0 - 6.0, 256.0 * 0.15, 256.0 * 0.15),
+        );
+        Ok(ReplayBrowserStruct {
+            element_rects: elements,
+            drawer,
+            error: None,
+            replays: ReplayBrowserStruct::find_replays(),
+            replay: None,
+        })
+    }
+    fn find_replays() -> Vec<PathBuf> {
+        let mut replays: Vec<(PathBuf, std::time::SystemTime)> = fs::read_dir(REPLAY_DIR)
+            .map(|dir| {
+                dir.filter_map(|entry| entry.ok())
+                    .map(|entry| entry.path())
+                    .filter(|path| path.extension().is_some_and(|ext| ext == "replay"))
+                    .filter_map(|path| {
+                        let modified = fs::metadata(&path).and_then(|m| m.modified()).ok()?;
+                        Some((path, modified))
+                    })
+                    .collect()
+            })
+            .unwrap_or_default();
+        replays.sort_by_key(|(_, modified)| std::cmp::Reverse(*modified));
+        replays.into_iter().map(|(path, _)| path).take(MAX_ROWS).collect()
+    }
+
+    fn row_rect(i: usize) -> Rect {
+        Rect::new(
+            (SCREEN_WIDTH - ROW_WIDTH) / 2.0,
+            LIST_Y + i as f32 * ROW_HEIGHT,
+            ROW_WIDTH,
+            ROW_HEIGHT,
+        )
+    }
+
+    pub fn draw(&self, canvas: &mut graphics::Canvas, ctx: &mut Context) -> GameResult {
+        self.drawer.draw_title(canvas, ctx)?;
+        self.drawer.draw_back_arrow_img(
+            canvas,
+            ctx,
+            *self.element_rects.get("BACK_ARROW_IMG").unwrap(),
+        )?;
+        if let Some(error) = &self.error {
+            let mut text = Text::new(error.as_str());
+            text.set_layout(TextLayout {
+                v_align: TextAlign::Begin,
+                h_align: TextAlign::Middle,
+            });
+            let y = LIST_Y - ROW_HEIGHT * 1.5;
+            canvas.draw(&text, DrawParam::from([SCREEN_WIDTH / 2.0, y]).color(Color::RED));
+        }
+        let mut rows = vec![];
+        if self.replays.is_empty() {
+            rows.push(format!("No replays in {}, press F5 in a match to record", REPLAY_DIR));
+        }
+        for path in self.replays.iter() {
+            rows.push(path.file_stem().unwrap_or_default().to_string_lossy().to_string());
+        }
+        for (i, row) in rows.into_iter().enumerate() {
+            let mut text = Text::new(row);
+            text.set_layout(TextLayout {
+                v_align: TextAlign::Begin,
+                h_align: TextAlign::Middle,
+            });
+            let y = ReplayBrowserStruct::row_rect(i).y;
+            canvas.draw(
+                &text,
+                DrawParam::from([SCREEN_WIDTH / 2.0, y]).color(Color::BLACK),
+            );
+        }
+        self.drawer
+            .draw_fps_counter(canvas, ctx)
+            .expect("Cant draw fps counter.");
+        Ok(())
+    }
+
+    pub fn check_mouse_click(
+        &mut self,
+        mouse_x: f32,
+        mouse_y: f32,
+        ctx: &mut Context,
+    ) -> Option<View> {
+        let mut new_view = None;
+        for (name, elem_rect) in &self.element_rects {
+            if elem_rect.contains([mouse_x, mouse_y]) && name == "BACK_ARROW_IMG" {
+                new_view = Some(View::MainMenu(MainMenuStruct::new(ctx).unwrap()));
+            }
+        }
+        for i in 0..self.replays.len() {
+            if !ReplayBrowserStruct::row_rect(i).contains([mouse_x, mouse_y]) {
+                continue;
+            }
+            // the state opens the game view with it
+            match Replay::load(&self.replays[i]) {
+                Ok(replay) => self.replay = Some(replay),
+                Err(e) => self.error = Some(format!("Cant play this replay: {}", e)),
+            }
+            break;
+        }
+        new_view
+    }
+}
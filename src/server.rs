@@ -1,147 +1,1449 @@
-use local_ip_address::local_ip;
+use crate::admin::{AdminCommand, AdminRequest, ADMIN_HELP};
+use crate::bot::{Bot, BotAction, BotSkill};
+use crate::discovery::DISCOVERY_PORTS;
+use crate::maze::{is_playable, name_from_path, random_location, read_maze};
+use crate::map_transfer::{split_map, MapChunk};
+use crate::password::{password_proof, PasswordKey};
+use crate::player::{Direction, Player, MOVE_INTERVAL};
+use crate::protocol::{quantise, DecodeError, PlayerId, PROTOCOL_VERSION};
+use crate::rate_limit::{RateLimit, RateLimiter};
+use crate::reliability::{Packet, Reliability};
+use crate::replay::{Origin, Recorder};
+use crate::snapshot::{PlayerState, Snapshot, SnapshotHistory, World, SNAPSHOT_HISTORY};
+use crate::transport::Transport;
+use glam::Vec2;
 use serde::{Deserialize, Serialize};
+use std::sync::atomic::{AtomicBool, Ordering};
+use throttle::Throttle;
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 use std::{
-    collections::HashMap,
-    net::{SocketAddr, UdpSocket},
-    str::FromStr,
-};
-use std::{
-    thread,
-    time::{Duration, Instant},
+    collections::{HashMap, HashSet, VecDeque},
+    fmt, io,
+    net::{IpAddr, SocketAddr, ToSocketAddrs, UdpSocket},
+    path::Path,
 };
 
-#[derive(Serialize, Deserialize, Debug)]
+pub const DEFAULT_PORT: u16 = 35353;
+pub const MAX_NAME_LENGTH: usize = 16;
+// most players a server takes, with the longest names OpponentList, full snapshots,
+// StatusResponse and HostMigration still fit one datagram
+pub const MAX_PLAYERS: usize = 16;
+// how long a lost players score and slot are kept for it to resume
+pub const SESSION_GRACE_PERIOD: Duration = Duration::from_secs(30);
+// goodbyes are not acked, they are sent a few times instead
+pub const FAREWELL_REPEATS: usize = 3;
+// how long a leaving host waits for each player it asks to take over
+pub const HANDOVER_TIMEOUT: Duration = Duration::from_secs(1);
+pub const HANDOVER_ATTEMPTS: usize = 3;
+// the world is advanced and sent to every client once per tick
+pub const TICK_INTERVAL: Duration = Duration::from_millis(50);
+// moves that arrive bunched up by the network are fine as long as the average rate is
+pub const MOVE_BURST: usize = 3;
+// sending the map costs this many packets of the peers rate limit
+pub const MAP_SEND_COST: f32 = 20.;
+// a joining client has this long to answer the password challenge
+pub const CHALLENGE_TIMEOUT: Duration = Duration::from_secs(10);
+// answering a status request costs this many packets of the peers rate limit
+pub const STATUS_COST: f32 = 5.;
+// how often the server logs its status line
+pub const STATUS_INTERVAL: Duration = Duration::from_secs(30);
+// a player may send this many chat lines per CHAT_INTERVAL
+pub const CHAT_BURST: usize = 3;
+pub const CHAT_INTERVAL: Duration = Duration::from_secs(5);
+// longest chat line, the server cuts longer ones
+pub const MAX_CHAT_LENGTH: usize = 80;
+// the bad datagrams of a host are forgotten after this long without another one
+pub const ERROR_MEMORY: Duration = Duration::from_secs(60);
 
+// Encoded by protocol.rs, players are referred to by the id the server gave them
+#[derive(Debug, Clone)]
 pub enum Message {
-    OpponentList(Vec<String>),
-    PlayerShot((String, String)),   //Shooters name, opponents name
-    ClientJoined((String, String)), // Name, ip-address
-    PlayerLeft(String),
-    PlayerMoved(String, (f32, f32), (f32, f32)), // Name, Coordinates(x,y), Direction (x, y)
-    Map(Vec<Vec<i32>>),
+    Join(u16, String, u64, bool), // Protocol version, name, answer to the PasswordChallenge (0 without one), spectator
+    JoinRejected(String), // Reason
+    Resume(u16, u64),          // Protocol version, session token from Welcome
+    Welcome((PlayerId, u64)),  // Id of the joining player, session token
+    OpponentList(Vec<(PlayerId, String)>),
+    PlayerShot((PlayerId, PlayerId)),   //Shooters id, opponents id
+    ShotRejected((PlayerId, PlayerId)), //Shooters id, opponents id
+    ClientJoined((PlayerId, String)),   // Id, name
+    PlayerLeft(PlayerId),
+    PlayerMoved(PlayerId, (u16, u16), Direction, u32), // Id, grid cell (x,y), Direction, move number
+    Map(Vec<Vec<i32>>), // reassembled from MapChunks on the client
+    MapChunk(MapChunk),
+    MapRequest, // client asks for the map again
     ConnectionLost,
-    Ping(String), //Client name
+    Ping, // the server knows the client by its address
     Pong,
+    MatchOver(String), //Winners name
+    Leave,             // the player quit, no grace period
+    ServerClosed,
+    DiscoveryRequest,
+    DiscoveryResponse(ServerInfo),
+    HostMigration(HostState), // the host leaves, the receiver starts a server with it
+    HostReady(u16),           // Port of the server that took over
+    ServerMoved(SocketAddr),  // resume the session at this address
+    Snapshot(Snapshot),       // the client turns it into a full snapshot before the game sees it
+    SnapshotAck(u32),         // Tick of the last snapshot the client applied
+    Respawn((u16, u16)),      // the server put the receiving player on this grid cell
+    PasswordChallenge(u64),   // Nonce the client signs with the password, see password.rs
+    Chat(PlayerId, String),   // Id of the speaking player, text
+    ServerNotice(String),     // shown in the chat log of every receiving player
+    StatusRequest,            // anyone may ask, no session needed
+    StatusResponse(ServerStatus),
+}
+
+// why the server turned a Join down, sent to the client as text
+#[derive(Debug)]
+pub enum JoinError {
+    VersionMismatch(u16), // protocol version of the client
+    EmptyName,
+    NameTooLong,
+    NameTaken(String),
+    ServerFull,
+    WrongPassword,
+    Banned,
+    SessionExpired,
+}
+
+impl fmt::Display for JoinError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            JoinError::VersionMismatch(version) => write!(
+                f,
+                "Version mismatch: server speaks protocol {}, client speaks {}",
+                PROTOCOL_VERSION, version
+            ),
+            JoinError::EmptyName => write!(f, "Enter a name first"),
+            JoinError::NameTooLong => {
+                write!(f, "Name is longer than {} letters", MAX_NAME_LENGTH)
+            }
+            JoinError::NameTaken(name) => write!(f, "{} is already playing", name),
+            JoinError::ServerFull => write!(f, "Server is full"),
+            JoinError::WrongPassword => write!(f, "Wrong password"),
+            JoinError::Banned => write!(f, "You are banned from this server"),
+            JoinError::SessionExpired => write!(f, "Connection lost for too long, join again"),
+        }
+    }
 }
+
+// why the server did not accept a move
+#[derive(Debug)]
+pub enum MoveError {
+    OutOfMaze,
+    Wall,
+    TooFar((u16, u16)), // cell the server has the player on
+    TooFast,
+}
+
+impl fmt::Display for MoveError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MoveError::OutOfMaze => write!(f, "outside the maze"),
+            MoveError::Wall => write!(f, "into a wall"),
+            MoveError::TooFar(from) => write!(f, "not next to {:?}", from),
+            MoveError::TooFast => write!(f, "faster than one cell per {:?}", MOVE_INTERVAL),
+        }
+    }
+}
+
+// what a server tells about itself to the LAN server browser
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ServerInfo {
+    pub name: String,
+    pub map: String,
+    pub players: usize,
+    pub max_players: usize,
+    pub port: u16,
+    pub locked: bool, // joining needs a password
+}
+
+// what a running server tells tools like maze-wars-query
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ServerStatus {
+    pub name: String,
+    pub map: String,
+    pub width: u16, // map size in cells
+    pub height: u16,
+    pub protocol_version: u16,
+    pub uptime_secs: u64,
+    pub max_players: usize,
+    pub spectators: usize,
+    pub locked: bool, // joining needs a password
+    pub players: Vec<PlayerStatus>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PlayerStatus {
+    pub name: String,
+    pub score: i32,
+    pub ping_ms: Option<u32>, // unknown until the client acked a snapshot
+}
+
+// the match as a leaving host hands it to the player taking over, the maze is
+// not part of it because every player already has it
+#[derive(Debug, Clone)]
+pub struct HostState {
+    pub config: ServerConfig,
+    pub next_id: PlayerId,
+    pub players: Vec<MigratedPlayer>,
+    pub spectators: Vec<(PlayerId, String, u64)>, // Id, name, session token
+}
+
+#[derive(Debug, Clone)]
+pub struct MigratedPlayer {
+    pub id: PlayerId,
+    pub name: String,
+    pub score: i32,
+    pub pos: (u16, u16),
+    pub dir: Direction,
+    pub token: u64, // session token, the player resumes with it on the new server
+}
+
+impl Message {
+    // messages that must arrive, lost ones are resent by the reliability layer
+    pub fn is_reliable(&self) -> bool {
+        matches!(
+            self,
+            Message::Welcome(_)
+                | Message::OpponentList(_)
+                | Message::PlayerShot(_)
+                | Message::ShotRejected(_)
+                | Message::ClientJoined(_)
+                | Message::PlayerLeft(_)
+                | Message::MapChunk(_)
+                | Message::MatchOver(_)
+                | Message::HostMigration(_)
+                | Message::ServerMoved(_)
+                | Message::Respawn(_)
+                | Message::Chat(..)
+                | Message::ServerNotice(_)
+        )
+    }
+}
+
+#[derive(Debug)]
+pub enum NetworkError {
+    Decode(DecodeError),
+    Send(io::Error),
+}
+
+impl fmt::Display for NetworkError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            NetworkError::Decode(e) => write!(f, "cant decode message: {}", e),
+            NetworkError::Send(e) => write!(f, "cant send message: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for NetworkError {}
+
+// Parses "ip", "ip:port", "[ipv6]:port" or "host[:port]", port defaults to DEFAULT_PORT
+pub fn resolve_address(input: &str) -> Option<SocketAddr> {
+    let input = input.trim();
+    if let Ok(address) = input.parse::<SocketAddr>() {
+        return Some(address);
+    }
+    if let Ok(ip) = input
+        .trim_matches(|c| c == '[' || c == ']')
+        .parse::<IpAddr>()
+    {
+        return Some(SocketAddr::new(ip, DEFAULT_PORT));
+    }
+    if let Some(address) = input.to_socket_addrs().ok().and_then(|mut a| a.next()) {
+        return Some(address);
+    }
+    (input, DEFAULT_PORT).to_socket_addrs().ok()?.next()
+}
+
+#[derive(Debug, Clone)]
+pub struct ServerConfig {
+    pub name: String,
+    pub map_name: String,
+    pub max_players: usize,
+    pub score_limit: Option<i32>, // match ends when a player reaches it
+    pub dedicated: bool,          // no owner playing inside the server process
+    pub password: Option<PasswordKey>, // made with password_key, the server never keeps the password
+    pub banned: HashSet<IpAddr>,
+    pub rate_limit: RateLimit,
+    pub record: Option<String>, // replay file every message of the match is written to
+    pub bots: usize,            // bots kept in the match while there are free slots
+    pub bot_skill: BotSkill,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        ServerConfig {
+            name: "Maze Wars".to_string(),
+            map_name: String::new(),
+            max_players: 8,
+            score_limit: None,
+            dedicated: false,
+            password: None,
+            banned: HashSet::new(),
+            rate_limit: RateLimit::default(),
+            record: None,
+            bots: 0,
+            bot_skill: BotSkill::default(),
+        }
+    }
+}
+
 pub struct Server {
-    owner: String,
-    pub socket: UdpSocket,
-    pub clients: HashMap<String, (String, Instant)>,
+    owner: Option<PlayerId>,
+    pub config: ServerConfig,
+    pub socket: Box<dyn Transport>, // a UdpSocket unless the server runs on a MemoryNetwork
+    pub clients: HashMap<PlayerId, (SocketAddr, Instant)>,
+    pub players: HashMap<PlayerId, Player>, // servers own copy of every players position
+    next_id: PlayerId,
+    sessions: HashMap<u64, PlayerId>,          // session token of every player
+    disconnected: HashMap<PlayerId, (Option<IpAddr>, Instant)>, // lost players that can still resume, from where they were last seen
+    maze: Vec<Vec<i32>>,
+    pub error_counts: HashMap<IpAddr, (u32, Instant)>, // failed datagrams per host and when the last one failed
+    reliability: Reliability,
+    last_cleanup: Instant,
+    stopped: Arc<AtomicBool>,
+    tick: u32,
+    last_tick: Instant,
+    history: SnapshotHistory,         // the world of the last ticks
+    acked: HashMap<PlayerId, u32>, // newest snapshot each client applied
+    moves: HashMap<PlayerId, u32>, // newest move of each player, older ones arrived too late
+    pub move_violations: HashMap<PlayerId, u32>, // rejected moves per player
+    respawned: HashSet<PlayerId>, // respawned players whose client did not move from the new cell yet
+    pub limiter: RateLimiter,
+    last_status: Instant,
+    challenges: HashMap<SocketAddr, (u64, Instant)>, // nonce sent to each joining address
+    tick_times: VecDeque<(u32, Instant)>, // when the last ticks were sent
+    pub pings: HashMap<PlayerId, Duration>, // round trip time from snapshot to ack per player
+    admin: (Sender<AdminRequest>, Receiver<AdminRequest>),
+    chat_throttles: HashMap<PlayerId, Throttle>,
+    pub spectators: HashMap<PlayerId, String>, // clients that watch without a player, by name
+    started: Instant,
+    recorder: Option<Recorder>,
+    pub bots: HashMap<PlayerId, Bot>, // players the server plays itself
 }
 
 impl Server {
-    pub fn new() -> Server {
-        let my_local_ip = local_ip().unwrap();
+    pub fn bind(address: SocketAddr, config: ServerConfig) -> io::Result<Server> {
+        Server::with_transport(Box::new(UdpSocket::bind(address)?), config)
+    }
+    pub fn with_transport(socket: Box<dyn Transport>, mut config: ServerConfig) -> io::Result<Server> {
+        config.max_players = config.max_players.min(MAX_PLAYERS);
         let server = Server {
-            owner: String::new(),
+            limiter: RateLimiter::new(config.rate_limit.clone()),
+            last_status: Instant::now(),
+            challenges: HashMap::new(),
+            tick_times: VecDeque::new(),
+            pings: HashMap::new(),
+            admin: channel(),
+            chat_throttles: HashMap::new(),
+            spectators: HashMap::new(),
+            started: Instant::now(),
+            recorder: None,
+            bots: HashMap::new(),
+            owner: None,
+            config,
             clients: HashMap::new(),
-            socket: UdpSocket::bind(my_local_ip.to_string() + ":35353").unwrap(),
+            players: HashMap::new(),
+            next_id: 1,
+            sessions: HashMap::new(),
+            disconnected: HashMap::new(),
+            maze: vec![],
+            error_counts: HashMap::new(),
+            reliability: Reliability::new(),
+            last_cleanup: Instant::now(),
+            stopped: Arc::new(AtomicBool::new(false)),
+            tick: 0,
+            last_tick: Instant::now(),
+            history: SnapshotHistory::default(),
+            acked: HashMap::new(),
+            moves: HashMap::new(),
+            move_violations: HashMap::new(),
+            respawned: HashSet::new(),
+            socket,
         };
-        let socket_clone = server.socket.try_clone().unwrap();
-        let server_ip = server.socket.local_addr().unwrap();
-        thread::spawn(move || loop {
-            let m = serde_json::to_vec(&Message::Pong).unwrap();
-            socket_clone.send_to(&m, &server_ip).unwrap();
-            thread::sleep(Duration::from_millis(1000))
-        });
+        // wake up often enough to keep the tick, resend lost messages and drop silent clients
         server
+            .socket
+            .set_read_timeout(Some(TICK_INTERVAL / 5))?;
+        Ok(server)
+    }
+    // binds to the first free port the server browser searches, any port after that
+    pub fn bind_free_port(ip: IpAddr, config: ServerConfig) -> io::Result<Server> {
+        for port in DEFAULT_PORT..DEFAULT_PORT + DISCOVERY_PORTS {
+            if let Ok(server) = Server::bind(SocketAddr::new(ip, port), config.clone()) {
+                return Ok(server);
+            }
+        }
+        Server::bind(SocketAddr::new(ip, 0), config)
+    }
+    // continues the match of a host that left, every player resumes with its old token
+    pub fn take_over(&mut self, state: HostState, owner: PlayerId) {
+        self.owner = Some(owner);
+        self.next_id = state.next_id;
+        for migrated in state.players {
+            let pos = (migrated.pos.0 as f32, migrated.pos.1 as f32);
+            let mut player = Server::new_player(migrated.id, migrated.name, pos);
+            player.dir = migrated.dir;
+            player.score = migrated.score;
+            self.players.insert(migrated.id, player);
+            self.sessions.insert(migrated.token, migrated.id);
+            self.disconnected.insert(migrated.id, (None, Instant::now()));
+        }
+        for (id, name, token) in state.spectators {
+            self.spectators.insert(id, name);
+            self.sessions.insert(token, id);
+            self.disconnected.insert(id, (None, Instant::now()));
+        }
     }
     pub fn start(&mut self, maze: Vec<Vec<i32>>) -> std::io::Result<()> {
+        self.open(maze);
+        while !self.stopped.load(Ordering::Relaxed) {
+            self.step()?;
+        }
+        self.shutdown();
+        Ok(())
+    }
+    // sets up the match, callers that run the loop themselves call step afterwards
+    pub fn open(&mut self, maze: Vec<Vec<i32>>) {
         println!("Starting server...");
         println!("Server IP: {:?}", self.socket.local_addr().unwrap());
         println!("");
-
+        self.maze = maze;
+        if let Some(path) = self.config.record.clone() {
+            match Recorder::create(Path::new(&path), Origin::Server) {
+                Ok(recorder) => {
+                    println!("Recording the match to {}", path);
+                    self.recorder = Some(recorder);
+                }
+                Err(e) => println!("Cant record to {}: {}", path, e),
+            }
+        }
+        self.fill_bots();
+    }
+    // one round of the server loop, waits for a datagram at most the read timeout
+    pub fn step(&mut self) -> io::Result<()> {
         let mut buf = [0; 2048];
-
-        loop {
-            let (amt, _src) = self.socket.recv_from(&mut buf)?;
-            let m: Message = serde_json::from_slice(&buf[..amt]).unwrap();
-
-            match &m {
-                Message::ClientJoined((name, ip_address)) => {
-                    if self.clients.len() == 0 {
-                        self.owner = name.to_owned();
+        self.housekeeping();
+        if let Some((src, messages)) = self.receive(&mut buf)? {
+            for m in messages {
+                self.handle_message(src, m);
+            }
+        }
+        Ok(())
+    }
+    // the next datagram, acked and decoded, None when nothing usable arrived
+    fn receive(&mut self, buf: &mut [u8]) -> io::Result<Option<(SocketAddr, Vec<Message>)>> {
+        let (amt, src) = match self.socket.recv_from(buf) {
+            Ok(received) => received,
+            Err(e) if Server::is_transient(&e) => return Ok(None),
+            Err(e) => return Err(e),
+        };
+        if !self.limiter.allow(src, 1.) {
+            return Ok(None);
+        }
+        let packet = match Packet::from_bytes(&buf[..amt]) {
+            Ok(packet) => packet,
+            Err(e) => {
+                self.register_error(src, &e);
+                return Ok(None);
+            }
+        };
+        // only joined clients have a reliable channel, Join and Resume are unreliable
+        if matches!(packet, Packet::Reliable(..)) && self.id_of(src).is_none() {
+            return Ok(None);
+        }
+        let (messages, ack) = self.reliability.receive(src, packet);
+        if let Some(ack) = ack {
+            self.send_bytes(src, &ack);
+        }
+        if let Some(recorder) = self.recorder.as_mut() {
+            for m in messages.iter() {
+                recorder.record(false, &src.to_string(), m);
+            }
+        }
+        Ok(Some((src, messages)))
+    }
+    // setting it to true makes start return, the socket is released when the server is dropped
+    pub fn stop_handle(&self) -> Arc<AtomicBool> {
+        self.stopped.clone()
+    }
+    // commands sent here run between two datagrams, see admin.rs
+    pub fn admin_handle(&self) -> Sender<AdminRequest> {
+        self.admin.0.clone()
+    }
+    // ends the match or hands it over, callers that run the loop themselves call it last
+    pub fn shutdown(&mut self) {
+        println!("Stopping server...");
+        // the host quit, another player keeps the match going if it can
+        if let Some(owner) = self.owner.take() {
+            self.remove_player(owner);
+            if let Some((successor, address)) = self.hand_over() {
+                println!("Player {} took over at {}", successor, address);
+                let others: Vec<SocketAddr> = self
+                    .clients
+                    .iter()
+                    .filter(|(id, _)| **id != successor)
+                    .map(|(_, client)| client.0)
+                    .collect();
+                for other in others {
+                    self.send(other, &Message::ServerMoved(address));
+                }
+                self.flush(HANDOVER_TIMEOUT);
+                return;
+            }
+        }
+        for _ in 0..FAREWELL_REPEATS {
+            self.send_to_all_clients(Message::ServerClosed);
+        }
+    }
+    // asks the players one after another to host the match, returns who took over and
+    // where the others can reach its server
+    fn hand_over(&mut self) -> Option<(PlayerId, SocketAddr)> {
+        let mut successors: Vec<PlayerId> = self.clients.keys().copied().collect();
+        successors.sort();
+        let state = self.host_state();
+        let mut buf = [0; 2048];
+        for successor in successors.into_iter().take(HANDOVER_ATTEMPTS) {
+            let address = self.clients[&successor].0;
+            self.send(address, &Message::HostMigration(state.clone()));
+            let asked = Instant::now();
+            while asked.elapsed() < HANDOVER_TIMEOUT {
+                for (peer, bytes) in self.reliability.resends() {
+                    self.send_bytes(peer, &bytes);
+                }
+                let (src, messages) = match self.receive(&mut buf) {
+                    Ok(Some(received)) => received,
+                    Ok(None) => continue,
+                    Err(_) => return None,
+                };
+                for m in messages {
+                    if let (Message::HostReady(port), true) = (m, src == address) {
+                        return Some((successor, SocketAddr::new(address.ip(), port)));
                     }
-                    self.clients
-                        .insert(name.clone(), (ip_address.clone(), Instant::now()));
-                    self.send_user_list(name);
-                    self.send_map(name, maze.clone());
-                    self.send_to_all_clients(m);
                 }
-                Message::PlayerMoved(_, _, _) => {
-                    self.send_to_all_clients(m);
+            }
+            println!("Player {} did not take over", successor);
+        }
+        None
+    }
+    fn host_state(&self) -> HostState {
+        let players = self
+            .sessions
+            .iter()
+            .filter(|(_, id)| self.clients.contains_key(id))
+            .filter_map(|(token, id)| self.players.get(id).map(|player| (token, player)))
+            .map(|(token, player)| MigratedPlayer {
+                id: player.id,
+                name: player.name.clone(),
+                score: player.score,
+                pos: quantise(player.pos),
+                dir: player.dir.clone(),
+                token: *token,
+            })
+            .collect();
+        let spectators = self
+            .sessions
+            .iter()
+            .filter(|(_, id)| self.clients.contains_key(id))
+            .filter_map(|(token, id)| self.spectators.get(id).map(|name| (*id, name.clone(), *token)))
+            .collect();
+        HostState {
+            config: self.config.clone(),
+            next_id: self.next_id,
+            players,
+            spectators,
+        }
+    }
+    // resends until everything was acked or the time is up
+    fn flush(&mut self, timeout: Duration) {
+        let started = Instant::now();
+        let mut buf = [0; 2048];
+        while self.reliability.has_pending() && started.elapsed() < timeout {
+            for (peer, bytes) in self.reliability.resends() {
+                self.send_bytes(peer, &bytes);
+            }
+            if self.receive(&mut buf).is_err() {
+                return;
+            }
+        }
+    }
+    fn handle_message(&mut self, src: SocketAddr, m: Message) {
+        match m {
+            Message::Join(version, name, proof, spectator) => {
+                if self.id_of(src).is_some() {
+                    return;
+                }
+                // the password never travels, the client proves it knows it
+                if version == PROTOCOL_VERSION && self.config.password.is_some() && proof == 0 {
+                    self.send_challenge(src);
+                    return;
+                }
+                let checked = self.check_join(src, version, &name, proof, spectator);
+                self.challenges.remove(&src);
+                if let Err(e) = checked {
+                    println!("Rejected {} from {}: {}", name, src, e);
+                    self.send(src, &Message::JoinRejected(e.to_string()));
+                    return;
                 }
-                Message::PlayerShot(_) => {
-                    self.send_to_all_clients(m);
+                if !self.limiter.allow(src, MAP_SEND_COST) {
+                    return;
                 }
-                Message::Ping(client_name) => {
-                    self.register_pong(client_name);
+                let id = self.next_id;
+                self.next_id = self.next_id.wrapping_add(1).max(1);
+                if self.clients.is_empty() && !self.config.dedicated && !spectator {
+                    self.owner = Some(id);
                 }
-                Message::Pong => {
-                    self.ping_pong_cleanup();
+                // reply to where the datagram came from, the client can not know
+                // under which address the server sees it
+                self.clients.insert(id, (src, Instant::now()));
+                let token = rand::random();
+                self.sessions.insert(token, id);
+                // spectators get everything the players get but nobody sees them
+                if spectator {
+                    println!("{} is watching from {}", name, src);
+                    self.spectators.insert(id, name);
+                    self.send(src, &Message::Welcome((id, token)));
+                    self.send_user_list(id);
+                    self.send_map_to(src, &self.maze.clone());
+                    return;
                 }
+                // a bot makes room for the player
+                if self.players.len() >= self.config.max_players {
+                    if let Some(bot) = self.bots.keys().max().copied() {
+                        self.remove_player(bot);
+                    }
+                }
+                self.players
+                    .insert(id, Server::new_player(id, name.clone(), (0., 0.)));
+                self.send(src, &Message::Welcome((id, token)));
+                self.send_user_list(id);
+                self.send_map_to(src, &self.maze.clone());
+                self.send_to_all_clients(Message::ClientJoined((id, name)));
+                self.respawn(id);
+            }
+            Message::Resume(version, token) => {
+                self.resume(src, version, token);
+            }
+            Message::PlayerMoved(id, cor, dir, seq) => {
+                // a client can only move its own player
+                if self.id_of(src) != Some(id) {
+                    return;
+                }
+                if self.moves.get(&id).is_some_and(|last| seq <= *last) {
+                    return;
+                }
+                // a rejected move is answered by the next snapshot, it puts the
+                // client back where the server has it
+                self.moves.insert(id, seq);
+                if let Err(e) = self.check_move(id, cor) {
+                    // made before the client applied its Respawn, from the old cell
+                    if self.respawned.contains(&id) {
+                        return;
+                    }
+                    let count = self.move_violations.entry(id).or_insert(0);
+                    *count += 1;
+                    println!(
+                        "Rejected move of player {} from {} to {:?} ({} rejected): {}",
+                        id, src, cor, count, e
+                    );
+                    return;
+                }
+                self.respawned.remove(&id);
+                // the others see it with the next snapshot
+                if let Some(player) = self.players.get_mut(&id) {
+                    player.pos = Vec2::new(cor.0 as f32, cor.1 as f32);
+                    player.dir = dir;
+                }
+            }
+            Message::PlayerShot((shooter, target)) => {
+                if self.id_of(src) != Some(shooter) {
+                    return;
+                }
+                if self.is_legal_shot(shooter, target) {
+                    self.hit(shooter, target);
+                } else {
+                    println!("Rejected shot from {} at {}", shooter, target);
+                    let rejection = Message::ShotRejected((shooter, target));
+                    self.send_to_client(shooter, &rejection);
+                }
+            }
+            Message::Chat(id, text) => {
+                if self.id_of(src) != Some(id) {
+                    return;
+                }
+                self.relay_chat(id, &text);
+            }
+            Message::Ping => {
+                self.register_pong(src);
+            }
+            Message::SnapshotAck(tick) => {
+                if let Some(id) = self.id_of(src) {
+                    let acked = self.acked.entry(id).or_insert(0);
+                    if tick > *acked {
+                        *acked = tick;
+                        self.register_ping(id, tick);
+                    }
+                }
+            }
+            Message::Leave => {
+                if let Some(id) = self.id_of(src) {
+                    self.remove_player(id);
+                }
+            }
+            Message::MapRequest if self.limiter.allow(src, MAP_SEND_COST) => {
+                self.send_map_to(src, &self.maze.clone());
+            }
+            Message::DiscoveryRequest => {
+                let info = self.info();
+                self.send(src, &Message::DiscoveryResponse(info));
+            }
+            Message::StatusRequest if self.limiter.allow(src, STATUS_COST) => {
+                let status = self.server_status();
+                self.send(src, &Message::StatusResponse(status));
+            }
 
+            _ => {}
+        };
+    }
+    fn housekeeping(&mut self) {
+        for (address, bytes) in self.reliability.resends() {
+            self.send_bytes(address, &bytes);
+        }
+        // a client that stops acking starts over with a Resume, like after a timeout
+        for address in self.reliability.failed_peers() {
+            if let Some(id) = self.id_of(address) {
+                println!("Lost client {}, it did not ack", id);
+                self.lose_client(id);
+            }
+        }
+        if self.last_cleanup.elapsed() >= Duration::from_secs(1) {
+            self.last_cleanup = Instant::now();
+            self.ping_pong_cleanup();
+            self.limiter.cleanup();
+            self.fill_bots();
+        }
+        while let Ok((command, reply)) = self.admin.1.try_recv() {
+            let answer = self.run_admin(command);
+            let _ = reply.send(answer);
+        }
+        if self.last_status.elapsed() >= STATUS_INTERVAL {
+            self.last_status = Instant::now();
+            println!("{}", self.status());
+        }
+        if self.last_tick.elapsed() >= TICK_INTERVAL {
+            // a server that fell far behind skips ticks instead of sending a burst
+            if self.last_tick.elapsed() >= TICK_INTERVAL * 5 {
+                self.last_tick = Instant::now();
+            } else {
+                self.last_tick += TICK_INTERVAL;
+            }
+            self.run_tick();
+        }
+    }
+    // sends every client what changed since the last snapshot it acked
+    fn run_tick(&mut self) {
+        self.run_bots();
+        self.tick += 1;
+        let world = self.world();
+        let clients: Vec<(PlayerId, SocketAddr)> =
+            self.clients.iter().map(|(id, client)| (*id, client.0)).collect();
+        for (id, address) in clients {
+            let baseline = self
+                .acked
+                .get(&id)
+                .and_then(|tick| self.history.get(*tick).map(|world| (*tick, world)));
+            let mut snapshot = Snapshot::diff(self.tick, baseline, &world);
+            // lets the client check its predicted moves against the server
+            snapshot.last_move = self.moves.get(&id).copied().unwrap_or(0);
+            self.send(address, &Message::Snapshot(snapshot));
+        }
+        self.history.push(self.tick, world);
+        if self.tick_times.len() == SNAPSHOT_HISTORY {
+            self.tick_times.pop_front();
+        }
+        self.tick_times.push_back((self.tick, Instant::now()));
+    }
+    // every bot moves or shoots, the snapshot of this tick shows it
+    fn run_bots(&mut self) {
+        let ids: Vec<PlayerId> = self.bots.keys().copied().collect();
+        for id in ids {
+            let me = match self.players.get(&id) {
+                Some(player) => player,
+                None => continue,
+            };
+            let others: Vec<&Player> = self
+                .players
+                .values()
+                .filter(|player| player.id != id && self.is_present(player.id))
+                .collect();
+            let action = self.bots.get_mut(&id).unwrap().think(me, &others, &self.maze);
+            match action {
+                Some(BotAction::Move(cell, dir)) => {
+                    if let Some(player) = self.players.get_mut(&id) {
+                        player.pos = Vec2::new(cell.0 as f32, cell.1 as f32);
+                        player.dir = dir;
+                    }
+                }
+                Some(BotAction::Shoot(target)) if self.is_legal_shot(id, target) => {
+                    self.hit(id, target);
+                }
                 _ => {}
+            }
+        }
+    }
+    // keeps config.bots bots in the match, a player that joins takes the place of one
+    fn fill_bots(&mut self) {
+        if self.maze.is_empty() {
+            return;
+        }
+        while self.bots.len() > self.config.bots {
+            let bot = *self.bots.keys().max().unwrap();
+            self.remove_player(bot);
+        }
+        while self.bots.len() < self.config.bots && self.players.len() < self.config.max_players {
+            self.add_bot();
+        }
+    }
+    fn add_bot(&mut self) {
+        let id = self.next_id;
+        self.next_id = self.next_id.wrapping_add(1).max(1);
+        let name = (1..)
+            .map(|n| format!("Bot {}", n))
+            .find(|name| self.find_player(name).is_none())
+            .unwrap();
+        println!("{} joined as player {}", name, id);
+        self.players
+            .insert(id, Server::new_player(id, name.clone(), (0., 0.)));
+        self.bots.insert(id, Bot::new(self.config.bot_skill.clone()));
+        self.send_to_all_clients(Message::ClientJoined((id, name)));
+        self.respawn(id);
+    }
+    // players that take part in the match, connected ones and bots
+    fn is_present(&self, id: PlayerId) -> bool {
+        self.clients.contains_key(&id) || self.bots.contains_key(&id)
+    }
+    // smoothed time from sending a tick to its ack, older acks arrive too late to count
+    fn register_ping(&mut self, id: PlayerId, tick: u32) {
+        let sent = match self.tick_times.iter().find(|(t, _)| *t == tick) {
+            Some((_, sent)) => *sent,
+            None => return,
+        };
+        let sample = sent.elapsed();
+        let ping = self.pings.entry(id).or_insert(sample);
+        *ping = (*ping * 7 + sample) / 8;
+    }
+    fn run_admin(&mut self, command: AdminCommand) -> String {
+        match command {
+            AdminCommand::Players => self.player_list(),
+            AdminCommand::Kick(name) => {
+                let id = match self.find_player(&name) {
+                    Some(id) => id,
+                    None => return format!("No player called {}", name),
+                };
+                if Some(id) == self.owner {
+                    return "The host can not be kicked".to_string();
+                }
+                self.kick(id, "You were kicked by the host");
+                self.notify(format!("{} was kicked", name))
+            }
+            AdminCommand::Ban(target) => {
+                // a lost player is banned from where it was last seen, it is unknown
+                // for players that were lost when the match was handed over
+                let (ip, named) = match target.parse::<IpAddr>() {
+                    Ok(ip) => (Some(ip), None),
+                    Err(_) => match self.find_player(&target) {
+                        Some(id) => {
+                            let connected = self.clients.get(&id).map(|client| client.0.ip());
+                            let lost = self.disconnected.get(&id).and_then(|lost| lost.0);
+                            (connected.or(lost), Some(id))
+                        }
+                        None => return format!("No player or address {}", target),
+                    },
+                };
+                let owner_ip = self.owner.and_then(|id| self.clients.get(&id)).map(|c| c.0.ip());
+                if ip.is_some() && owner_ip == ip {
+                    return "The host can not be banned".to_string();
+                }
+                let mut banned: Vec<PlayerId> = named.into_iter().collect();
+                if let Some(ip) = ip {
+                    self.config.banned.insert(ip);
+                    let connected = self.clients.iter().filter(|(_, client)| client.0.ip() == ip);
+                    let lost = self.disconnected.iter().filter(|(_, lost)| lost.0 == Some(ip));
+                    banned.extend(connected.map(|(id, _)| *id).chain(lost.map(|(id, _)| *id)));
+                }
+                banned.sort();
+                banned.dedup();
+                for id in banned.iter() {
+                    self.kick(*id, &JoinError::Banned.to_string());
+                }
+                match ip {
+                    Some(ip) => self.notify(format!("Banned {}, {} players removed", ip, banned.len())),
+                    None => self.notify(format!("Removed {}, its address is not known", target)),
+                }
+            }
+            AdminCommand::ChangeMap(path) => {
+                let maze = match read_maze(&path) {
+                    Ok(maze) => maze,
+                    Err(e) => return format!("Cant read map {}: {}", path, e),
+                };
+                if !is_playable(&maze) {
+                    return format!("{} has no room to play", path);
+                }
+                self.maze = maze;
+                self.config.map_name = name_from_path(&path);
+                let addresses: Vec<SocketAddr> = self.clients.values().map(|c| c.0).collect();
+                for address in addresses {
+                    self.send_map_to(address, &self.maze.clone());
+                }
+                self.restart_match();
+                self.notify(format!("Playing on {} now", self.config.map_name))
+            }
+            AdminCommand::Restart => {
+                self.restart_match();
+                self.notify("Match restarted".to_string())
+            }
+            AdminCommand::ScoreLimit(limit) => {
+                self.config.score_limit = limit;
+                let notice = match limit {
+                    Some(limit) => format!("Matches end at {} points", limit),
+                    None => "Matches have no score limit".to_string(),
+                };
+                self.notify(notice)
+            }
+            AdminCommand::Bots(count, skill) => {
+                if let Some(skill) = skill {
+                    for bot in self.bots.values_mut() {
+                        bot.skill = skill.clone();
+                    }
+                    self.config.bot_skill = skill;
+                }
+                self.config.bots = count;
+                self.fill_bots();
+                self.notify(format!("Playing with {} bots", self.bots.len()))
+            }
+            AdminCommand::Help => ADMIN_HELP.to_string(),
+        }
+    }
+    // passes a chat line on to everyone, the speaker sees it in the same order as the others
+    fn relay_chat(&mut self, id: PlayerId, text: &str) {
+        let text: String = text
+            .trim()
+            .chars()
+            .filter(|c| !c.is_control())
+            .take(MAX_CHAT_LENGTH)
+            .collect();
+        if text.is_empty() {
+            return;
+        }
+        let throttle = self
+            .chat_throttles
+            .entry(id)
+            .or_insert_with(|| Throttle::new(CHAT_INTERVAL, CHAT_BURST));
+        if throttle.accept().is_err() {
+            let notice = Message::ServerNotice("You are sending messages too fast".to_string());
+            self.send_to_client(id, &notice);
+            return;
+        }
+        // the players do not know the names of spectators
+        let message = match self.spectators.get(&id) {
+            Some(name) => Message::ServerNotice(format!("{} (watching): {}", name, text)),
+            None => Message::Chat(id, text),
+        };
+        self.send_to_all_clients(message);
+    }
+    // one line per player with the address it plays from and its ping
+    fn player_list(&self) -> String {
+        let mut players: Vec<&Player> = self.players.values().collect();
+        players.sort_by_key(|player| player.id);
+        let playing = self.clients.len() - self.connected_spectators() + self.bots.len();
+        let mut list = format!("{}/{} players", playing, self.config.max_players);
+        for player in players {
+            let address = match self.clients.get(&player.id) {
+                Some(client) => client.0.to_string(),
+                None if self.bots.contains_key(&player.id) => "bot".to_string(),
+                None => "lost".to_string(),
+            };
+            let ping = match self.pings.get(&player.id) {
+                Some(ping) => format!("{} ms", ping.as_millis()),
+                None => "-".to_string(),
+            };
+            let host = if Some(player.id) == self.owner { " (host)" } else { "" };
+            list += &format!(
+                "\n{:>3} {:16} {:22} {:5} {}{}",
+                player.id, player.name, address, player.score, ping, host
+            );
+        }
+        let mut spectators: Vec<(&PlayerId, &String)> = self.spectators.iter().collect();
+        spectators.sort();
+        for (id, name) in spectators {
+            let address = match self.clients.get(id) {
+                Some(client) => client.0.to_string(),
+                None => "lost".to_string(),
             };
+            list += &format!("\n{:>3} {:16} {:22} watching", id, name, address);
+        }
+        list
+    }
+    // tells every player what the admin did, returns the text for the admin as well
+    fn notify(&mut self, notice: String) -> String {
+        self.send_to_all_clients(Message::ServerNotice(notice.clone()));
+        notice
+    }
+    // a player or a spectator
+    fn find_player(&self, name: &str) -> Option<PlayerId> {
+        let spectator = self
+            .spectators
+            .iter()
+            .find(|(_, spectator)| spectator.eq_ignore_ascii_case(name))
+            .map(|(id, _)| *id);
+        self.players
+            .values()
+            .find(|player| player.name.eq_ignore_ascii_case(name))
+            .map(|player| player.id)
+            .or(spectator)
+    }
+    // the client leaves the match with the reason, like after a rejected join
+    fn kick(&mut self, id: PlayerId, reason: &str) {
+        println!("Kicking player {}: {}", id, reason);
+        for _ in 0..FAREWELL_REPEATS {
+            self.send_to_client(id, &Message::JoinRejected(reason.to_string()));
+        }
+        self.remove_player(id);
+    }
+    // everyone starts again from zero on a new spot
+    fn restart_match(&mut self) {
+        let ids: Vec<PlayerId> = self.players.keys().copied().collect();
+        for id in ids {
+            if let Some(player) = self.players.get_mut(&id) {
+                player.score = 0;
+            }
+            self.respawn(id);
+        }
+    }
+    // the connected players as every client should see them
+    fn world(&self) -> World {
+        self.players
+            .values()
+            .filter(|player| self.is_present(player.id))
+            .map(|player| {
+                let state = PlayerState {
+                    pos: quantise(player.pos),
+                    dir: player.dir.clone(),
+                    score: player.score,
+                };
+                (player.id, state)
+            })
+            .collect()
+    }
+    // the servers copy of a player, its throttle allows short bursts of moves
+    fn new_player(id: PlayerId, name: String, pos: (f32, f32)) -> Player {
+        let mut player = Player::new(id, name, pos);
+        player.moving_throttle = Throttle::new(MOVE_INTERVAL * MOVE_BURST as u32, MOVE_BURST);
+        player
+    }
+    // a player may turn on its cell or step onto a free neighbouring cell
+    fn check_move(&mut self, id: PlayerId, cell: (u16, u16)) -> Result<(), MoveError> {
+        let player = match self.players.get_mut(&id) {
+            Some(player) => player,
+            None => return Ok(()),
+        };
+        let tile = self
+            .maze
+            .get(cell.1 as usize)
+            .and_then(|row| row.get(cell.0 as usize));
+        match tile {
+            None => return Err(MoveError::OutOfMaze),
+            Some(0) => {}
+            Some(_) => return Err(MoveError::Wall),
+        }
+        let from = quantise(player.pos);
+        let distance = from.0.abs_diff(cell.0) + from.1.abs_diff(cell.1);
+        if distance > 1 {
+            return Err(MoveError::TooFar(from));
+        }
+        if distance == 1 && player.moving_throttle.accept().is_err() {
+            return Err(MoveError::TooFast);
+        }
+        Ok(())
+    }
+    // puts the player on a free cell, the client moves its own player there
+    fn respawn(&mut self, id: PlayerId) {
+        // maps are checked before the server plays on them
+        let (x, y) = match random_location(&self.maze) {
+            Some(location) => location,
+            None => {
+                println!("No free cell to respawn player {}", id);
+                return;
+            }
+        };
+        if let Some(player) = self.players.get_mut(&id) {
+            player.pos = Vec2::new(x, y);
+        }
+        if let Some(bot) = self.bots.get_mut(&id) {
+            bot.reset();
+        }
+        if self.clients.contains_key(&id) {
+            self.respawned.insert(id);
+        }
+        self.send_to_client(id, &Message::Respawn(quantise(Vec2::new(x, y))));
+    }
+    pub fn info(&self) -> ServerInfo {
+        ServerInfo {
+            name: self.config.name.clone(),
+            map: self.config.map_name.clone(),
+            players: self.clients.len() - self.connected_spectators() + self.bots.len(),
+            max_players: self.config.max_players,
+            port: self.socket.local_addr().map(|a| a.port()).unwrap_or(0),
+            locked: self.config.password.is_some(),
+        }
+    }
+    pub fn server_status(&self) -> ServerStatus {
+        let mut players: Vec<&Player> = self
+            .players
+            .values()
+            .filter(|player| self.is_present(player.id))
+            .collect();
+        players.sort_by_key(|player| player.id);
+        ServerStatus {
+            name: self.config.name.clone(),
+            map: self.config.map_name.clone(),
+            width: self.maze.first().map_or(0, |row| row.len()) as u16,
+            height: self.maze.len() as u16,
+            protocol_version: PROTOCOL_VERSION,
+            uptime_secs: self.started.elapsed().as_secs(),
+            max_players: self.config.max_players,
+            spectators: self.connected_spectators(),
+            locked: self.config.password.is_some(),
+            players: players
+                .into_iter()
+                .map(|player| PlayerStatus {
+                    name: player.name.clone(),
+                    score: player.score,
+                    ping_ms: self.pings.get(&player.id).map(|ping| ping.as_millis() as u32),
+                })
+                .collect(),
+        }
+    }
+    fn connected_spectators(&self) -> usize {
+        self.spectators
+            .keys()
+            .filter(|id| self.clients.contains_key(id))
+            .count()
+    }
+    // the same nonce for every Join until it was answered
+    fn send_challenge(&mut self, address: SocketAddr) {
+        let nonce = self
+            .challenges
+            .entry(address)
+            .or_insert_with(|| (rand::random::<u64>().max(1), Instant::now()))
+            .0;
+        self.send(address, &Message::PasswordChallenge(nonce));
+    }
+    // one line about the players and the traffic the rate limit dropped
+    pub fn status(&self) -> String {
+        let mut status = format!(
+            "Status: {}/{} players, {} watching, {} packets dropped",
+            self.clients.len() - self.connected_spectators() + self.bots.len(),
+            self.config.max_players,
+            self.connected_spectators(),
+            self.limiter.dropped_total()
+        );
+        let mut dropped: Vec<(&IpAddr, &u64)> = self.limiter.dropped.iter().collect();
+        dropped.sort_by(|a, b| b.1.cmp(a.1));
+        for (address, count) in dropped.iter().take(5) {
+            status += &format!(", {} from {}", count, address);
+        }
+        let banned = self.limiter.banned();
+        if !banned.is_empty() {
+            status += &format!(", banned for flooding: {:?}", banned);
+        }
+        status
+    }
+    // address other processes on this machine can use to reach the server
+    pub fn connect_address(&self) -> io::Result<SocketAddr> {
+        let mut address = self.socket.local_addr()?;
+        if address.ip().is_unspecified() {
+            let loopback = match address {
+                SocketAddr::V4(_) => IpAddr::from([127, 0, 0, 1]),
+                SocketAddr::V6(_) => IpAddr::from([0, 0, 0, 0, 0, 0, 0, 1]),
+            };
+            address.set_ip(loopback);
+        }
+        Ok(address)
+    }
+    // id of the player playing from this address
+    fn id_of(&self, address: SocketAddr) -> Option<PlayerId> {
+        self.clients
+            .iter()
+            .find(|(_, (client_address, _))| *client_address == address)
+            .map(|(id, _)| *id)
+    }
+    // gives a lost player its slot back, the client keeps its map and game
+    fn resume(&mut self, src: SocketAddr, version: u16, token: u64) {
+        if version != PROTOCOL_VERSION {
+            let reason = JoinError::VersionMismatch(version).to_string();
+            self.send(src, &Message::JoinRejected(reason));
+            return;
+        }
+        if self.config.banned.contains(&src.ip()) {
+            self.send(src, &Message::JoinRejected(JoinError::Banned.to_string()));
+            return;
+        }
+        let id = match self.sessions.get(&token) {
+            Some(id) => *id,
+            None => {
+                self.send(src, &Message::JoinRejected(JoinError::SessionExpired.to_string()));
+                return;
+            }
+        };
+        // the Welcome is already on its way
+        if self.clients.get(&id).map(|client| client.0) == Some(src) {
+            return;
+        }
+        // both sides start over with fresh sequence numbers
+        if let Some((old_address, _)) = self.clients.insert(id, (src, Instant::now())) {
+            self.reliability.forget(&old_address);
+        }
+        self.reliability.forget(&src);
+        let returned = self.disconnected.remove(&id).is_some();
+        // the client starts over with a full snapshot
+        self.acked.remove(&id);
+        println!("Player {} resumed from {}", id, src);
+
+        self.send(src, &Message::Welcome((id, token)));
+        self.send_user_list(id);
+        // the map may have changed while it was gone
+        self.send_map_to(src, &self.maze.clone());
+        // the others were told it left, see lose_client
+        if let Some(player) = self.players.get(&id).filter(|_| returned) {
+            let joined = Message::ClientJoined((id, player.name.clone()));
+            let others: Vec<SocketAddr> = self
+                .clients
+                .iter()
+                .filter(|(other, _)| **other != id)
+                .map(|(_, client)| client.0)
+                .collect();
+            for other in others {
+                self.send(other, &joined);
+            }
         }
     }
     fn ping_pong_cleanup(&mut self) {
+        self.challenges
+            .retain(|_, (_, sent)| sent.elapsed() < CHALLENGE_TIMEOUT);
+        self.error_counts
+            .retain(|_, (_, last)| last.elapsed() < ERROR_MEMORY);
         let mut remove_clinets = vec![];
         for (client, (_, time)) in &self.clients {
             let duration = time.elapsed();
-            if duration > Duration::new(2, 0) && *client != self.owner {
-                remove_clinets.push(client.to_owned());
+            if duration > Duration::new(2, 0) && Some(*client) != self.owner {
+                remove_clinets.push(*client);
             }
         }
-        for client in remove_clinets.iter() {
-            println!("Remove client {}", client);
-            self.clients.remove(client);
-            self.send_to_all_clients(Message::PlayerLeft(client.to_owned()));
+        for client in remove_clinets {
+            println!("Lost client {}", client);
+            self.lose_client(client);
+        }
+        let expired: Vec<PlayerId> = self
+            .disconnected
+            .iter()
+            .filter(|(_, (_, since))| since.elapsed() > SESSION_GRACE_PERIOD)
+            .map(|(id, _)| *id)
+            .collect();
+        for id in expired {
+            self.remove_player(id);
         }
     }
-    fn register_pong(&mut self, client_name: &String) {
-        let client = self.clients.get_mut(client_name);
-        if client.is_none() {
-            return;
+    // the player keeps its slot and score until the grace period is over, its client resumes
+    fn lose_client(&mut self, id: PlayerId) {
+        let address = self.clients.remove(&id).map(|client| client.0);
+        if let Some(address) = address {
+            self.reliability.forget(&address);
+        }
+        self.disconnected
+            .insert(id, (address.map(|address| address.ip()), Instant::now()));
+        if !self.spectators.contains_key(&id) {
+            self.send_to_all_clients(Message::PlayerLeft(id));
+        }
+    }
+    fn remove_player(&mut self, id: PlayerId) {
+        println!("Remove client {}", id);
+        if let Some((address, _)) = self.clients.remove(&id) {
+            self.reliability.forget(&address);
+            if !self.spectators.contains_key(&id) {
+                self.send_to_all_clients(Message::PlayerLeft(id));
+            }
         }
+        if self.bots.remove(&id).is_some() {
+            self.send_to_all_clients(Message::PlayerLeft(id));
+        }
+        self.spectators.remove(&id);
+        self.disconnected.remove(&id);
+        self.acked.remove(&id);
+        self.moves.remove(&id);
+        self.move_violations.remove(&id);
+        self.respawned.remove(&id);
+        self.pings.remove(&id);
+        self.chat_throttles.remove(&id);
+        self.players.remove(&id);
+        self.sessions.retain(|_, session| *session != id);
+    }
+    fn register_pong(&mut self, address: SocketAddr) {
+        let client = match self.id_of(address) {
+            Some(id) => self.clients.get_mut(&id),
+            None => return,
+        };
         client.unwrap().1 = Instant::now();
+        // lets the client notice when the server goes away
+        self.send(address, &Message::Pong);
     }
-    fn send_map(&self, client: &String, maze: Vec<Vec<i32>>) {
-        let msg = Message::Map(maze);
-        let m = serde_json::to_vec(&msg).unwrap();
-        let clien_socket = &self.clients.get(client).unwrap().0;
-        self.socket
-            .send_to(
-                &m,
-                SocketAddr::from_str(&clien_socket).expect("Cant send data to all clients."),
-            )
-            .unwrap();
+    // re-runs the clients line of sight walk on the servers copy of the world
+    fn is_legal_shot(&self, shooter: PlayerId, target: PlayerId) -> bool {
+        let shooter = match self.players.get(&shooter) {
+            Some(player) => player,
+            None => return false,
+        };
+        let others: Vec<&Player> = self
+            .players
+            .values()
+            .filter(|player| player.id != shooter.id && self.is_present(player.id))
+            .collect();
+        match shooter.first_in_line_of_sight(&self.maze, others.iter().copied()) {
+            Some(i) => others[i].id == target,
+            None => false,
+        }
+    }
+    // a legal shot, everyone sees it and the target starts somewhere else
+    fn hit(&mut self, shooter: PlayerId, target: PlayerId) {
+        self.register_shot(shooter, target);
+        self.send_to_all_clients(Message::PlayerShot((shooter, target)));
+        self.respawn(target);
+        self.check_score_limit(shooter);
+    }
+    fn register_shot(&mut self, shooter: PlayerId, target: PlayerId) {
+        if let Some(player) = self.players.get_mut(&shooter) {
+            player.shot_opponent();
+        }
+        if let Some(player) = self.players.get_mut(&target) {
+            player.got_shot();
+        }
+    }
+    fn check_score_limit(&mut self, shooter: PlayerId) {
+        let limit = match self.config.score_limit {
+            Some(limit) => limit,
+            None => return,
+        };
+        let winner = match self.players.get(&shooter) {
+            Some(player) if player.score >= limit => player.name.clone(),
+            _ => return,
+        };
+        println!("{} won the match", winner);
+        for player in self.players.values_mut() {
+            player.score = 0;
+        }
+        self.send_to_all_clients(Message::MatchOver(winner));
+    }
+    fn check_join(
+        &self,
+        address: SocketAddr,
+        version: u16,
+        name: &str,
+        proof: u64,
+        spectator: bool,
+    ) -> Result<(), JoinError> {
+        if version != PROTOCOL_VERSION {
+            return Err(JoinError::VersionMismatch(version));
+        }
+        if self.config.banned.contains(&address.ip()) {
+            return Err(JoinError::Banned);
+        }
+        if let Some(key) = self.config.password {
+            let nonce = self.challenges.get(&address).map(|challenge| challenge.0);
+            if nonce.is_none_or(|nonce| password_proof(key, nonce) != proof) {
+                return Err(JoinError::WrongPassword);
+            }
+        }
+        if name.trim().is_empty() {
+            return Err(JoinError::EmptyName);
+        }
+        if name.chars().count() > MAX_NAME_LENGTH {
+            return Err(JoinError::NameTooLong);
+        }
+        if self.find_player(name).is_some() {
+            return Err(JoinError::NameTaken(name.to_string()));
+        }
+        // lost players that can still resume keep their slot, spectators take none
+        // and bots make room
+        if !spectator && self.players.len() - self.bots.len() >= self.config.max_players {
+            return Err(JoinError::ServerFull);
+        }
+        Ok(())
+    }
+    fn send_to_client(&mut self, client: PlayerId, msg: &Message) {
+        let address = match self.clients.get(&client) {
+            Some(client) => client.0,
+            None => return,
+        };
+        self.send(address, msg);
+    }
+    // the maze does not fit into one datagram, it is sent in numbered chunks
+    fn send_map_to(&mut self, address: SocketAddr, maze: &[Vec<i32>]) {
+        for chunk in split_map(maze) {
+            self.send(address, &Message::MapChunk(chunk));
+        }
     }
-    fn send_user_list(&self, client: &String) {
+    fn send_user_list(&mut self, client: PlayerId) {
         // send message back to sender
-        let list = Vec::from_iter(self.clients.keys())
-            .iter()
-            .map(|&value| value.to_owned())
+        let list = self
+            .players
+            .values()
+            .filter(|player| self.is_present(player.id))
+            .map(|player| (player.id, player.name.clone()))
             .collect();
-        let msg = Message::OpponentList(list);
-        let m = serde_json::to_vec(&msg).unwrap();
-        let clien_socket = &self.clients.get(client).unwrap().0;
-        self.socket
-            .send_to(
-                &m,
-                SocketAddr::from_str(&clien_socket).expect("Cant send data to all clients."),
-            )
-            .unwrap();
+        self.send_to_client(client, &Message::OpponentList(list));
+    }
+    fn send_to_all_clients(&mut self, msg: Message) {
+        let addresses: Vec<SocketAddr> = self.clients.values().map(|c| c.0).collect();
+        for address in addresses {
+            self.send(address, &msg);
+        }
     }
-    fn send_to_all_clients(&self, msg: Message) {
-        let m = serde_json::to_vec(&msg).unwrap();
-        for client in &self.clients {
-            self.socket
-                .send_to(
-                    &m,
-                    SocketAddr::from_str(&client.1 .0).expect("Cant send data to all clients."),
-                )
-                .unwrap();
+    fn send(&mut self, address: SocketAddr, msg: &Message) {
+        let bytes = self.reliability.prepare(address, msg.clone());
+        self.send_bytes(address, &bytes);
+        if let Some(recorder) = self.recorder.as_mut() {
+            recorder.record(true, &address.to_string(), msg);
         }
     }
+    fn send_bytes(&mut self, address: SocketAddr, bytes: &[u8]) {
+        if let Err(e) = self.socket.send_to(bytes, address) {
+            self.register_error(address, &NetworkError::Send(e));
+        }
+    }
+    fn register_error(&mut self, address: SocketAddr, error: &NetworkError) {
+        let (count, last) = self
+            .error_counts
+            .entry(address.ip())
+            .or_insert((0, Instant::now()));
+        *count += 1;
+        *last = Instant::now();
+        println!(
+            "Bad datagram from/to {} ({} errors): {}",
+            address, count, error
+        );
+    }
+    // errors caused by a single peer, like an unreachable client, should not stop the server
+    fn is_transient(error: &io::Error) -> bool {
+        matches!(
+            error.kind(),
+            io::ErrorKind::ConnectionReset
+                | io::ErrorKind::ConnectionRefused
+                | io::ErrorKind::Interrupted
+                | io::ErrorKind::WouldBlock
+                | io::ErrorKind::TimedOut
+        )
+    }
 }
@@ -0,0 +1,240 @@
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::mpsc::{channel, Receiver};
+use std::thread;
+use std::time::Duration;
+
+use ggez::{
+    graphics::{self, Color, DrawParam, Rect, Text, TextAlign, TextLayout},
+    Context, GameResult,
+};
+
+use crate::{
+    discovery,
+    drawer::{Button, Drawer, Input},
+    server::ServerInfo,
+    view::View,
+    SCREEN_WIDTH,
+};
+
+use super::{join_game::JoinGameStruct, main_menu::MainMenuStruct};
+
+const LIST_Y: f32 = 350.0;
+const ROW_HEIGHT: f32 = 20.0;
+const ROW_WIDTH: f32 = 400.0;
+
+pub struct ServerBrowserStruct {
+    pub element_rects: HashMap<String, Rect>, // holds text input and button rects
+    pub name_input_active: bool,
+    pub name: Text,
+    pub drawer: Drawer,
+    pub error: Option<String>,
+    pub servers: Vec<(SocketAddr, ServerInfo)>,
+    pub server_address: Option<SocketAddr>, // the clicked server
+    pub connecting: bool,                   // waiting for it to accept us and send the map
+    search: Option<Receiver<(SocketAddr, ServerInfo)>>,
+}
+
+impl ServerBrowserStruct {
+    pub fn new(ctx: &mut Context) -> GameResult<ServerBrowserStruct> {
+        let drawer = Drawer::new(ctx)?;
+        let mut browser = ServerBrowserStruct {
+            element_rects: Self::get_elements(&drawer.button_dimensions, &drawer.input_dimensions),
+            name_input_active: false,
+            name: Text::new(""),
+            drawer,
+            error: None,
+            servers: vec![],
+            server_address: None,
+            connecting: false,
+            search: None,
+        };
+        browser.refresh();
+        Ok(browser)
+    }
+    // starts a new search in the background, answers are picked up by poll_servers
+    pub fn refresh(&mut self) {
+        self.servers.clear();
+        let (found, receiver) = channel();
+        self.search = Some(receiver);
+        thread::spawn(move || {
+            if let Err(e) = discovery::search(found, Duration::from_secs(1)) {
+                println!("Server discovery failed: {}", e);
+            }
+        });
+    }
+    pub fn poll_servers(&mut self) {
+        let search = match &self.search {
+            Some(search) => search,
+            None => return,
+        };
+        while let Ok((address, info)) = search.try_recv() {
+            let known = self.servers.iter().any(|(known_address, known_info)| {
+                *known_address == address
+                    || (known_address.port() == address.port()
+                        && known_info.name == info.name
+                        && discovery::is_this_machine(&known_address.ip())
+                        && discovery::is_this_machine(&address.ip()))
+            });
+            if !known {
+                self.servers.push((address, info));
+            }
+        }
+    }
+    pub fn get_elements(
+        button_dimensions: &Button,
+        input_dimensions: &Input,
+    ) -> HashMap<String, Rect> {
+        let mut elems = HashMap::new();
+
+        elems.insert(
+            "NAME_INPUT".to_string(),
+            Rect::new(
+                input_dimensions.horizontal_offset,
+                200.0,
+                input_dimensions.width,
+                input_dimensions.height,
+            ),
+        );
+        elems.insert(
+            "REFRESH".to_string(),
+            Rect::new(
+                button_dimensions.horizontal_offset,
+                275.0,
+                button_dimensions.width,
+                button_dimensions.height,
+            ),
+        );
+        elems.insert(
+            "BACK_ARROW_IMG".to_string(),
+            Rect::new(100.0 - 6.0, 100.0 - 6.0, 256.0 * 0.15, 256.0 * 0.15),
+        );
+
+        elems
+    }
+
+    fn row_rect(i: usize) -> Rect {
+        Rect::new(
+            (SCREEN_WIDTH - ROW_WIDTH) / 2.0,
+            LIST_Y + i as f32 * ROW_HEIGHT,
+            ROW_WIDTH,
+            ROW_HEIGHT,
+        )
+    }
+
+    pub fn draw(&self, canvas: &mut graphics::Canvas, ctx: &mut Context) -> GameResult {
+        self.drawer.draw_title(canvas, ctx)?;
+        self.drawer.draw_name_input(
+            canvas,
+            ctx,
+            200.0,
+            self.name.contents(),
+            self.name_input_active,
+            *self.element_rects.get("NAME_INPUT").unwrap(),
+        )?;
+        self.drawer.draw_back_arrow_img(
+            canvas,
+            ctx,
+            *self.element_rects.get("BACK_ARROW_IMG").unwrap(),
+        )?;
+        self.drawer.draw_button(
+            canvas,
+            ctx,
+            275.0,
+            *self.element_rects.get("REFRESH").unwrap(),
+            "Refresh",
+        )?;
+
+        let status = if self.connecting {
+            Some(("Connecting...", Color::BLACK))
+        } else {
+            self.error.as_deref().map(|error| (error, Color::RED))
+        };
+        if let Some((status, color)) = status {
+            let mut text = Text::new(status);
+            text.set_layout(TextLayout {
+                v_align: TextAlign::Begin,
+                h_align: TextAlign::Middle,
+            });
+            let y = LIST_Y - ROW_HEIGHT * 1.5;
+            canvas.draw(&text, DrawParam::from([SCREEN_WIDTH / 2.0, y]).color(color));
+        }
+        let mut rows = vec![];
+        if self.servers.is_empty() {
+            rows.push("No games found".to_string());
+        }
+        for (address, info) in self.servers.iter() {
+            let lock = if info.locked { "  (password)" } else { "" };
+            rows.push(format!(
+                "{}  {}  {}/{}  {}{}",
+                info.name, info.map, info.players, info.max_players, address, lock
+            ));
+        }
+        for (i, row) in rows.into_iter().enumerate() {
+            let mut text = Text::new(row);
+            text.set_layout(TextLayout {
+                v_align: TextAlign::Begin,
+                h_align: TextAlign::Middle,
+            });
+            let y = ServerBrowserStruct::row_rect(i).y;
+            canvas.draw(
+                &text,
+                DrawParam::from([SCREEN_WIDTH / 2.0, y]).color(Color::BLACK),
+            );
+        }
+        self.drawer
+            .draw_fps_counter(canvas, ctx)
+            .expect("Cant draw fps counter.");
+        Ok(())
+    }
+
+    pub fn check_mouse_click(
+        &mut self,
+        mouse_x: f32,
+        mouse_y: f32,
+        ctx: &mut Context,
+    ) -> Option<View> {
+        let mut new_view = None;
+        let mut refresh = false;
+        for (name, elem_rect) in &self.element_rects {
+            if elem_rect.contains([mouse_x, mouse_y]) {
+                if name == "NAME_INPUT" {
+                    self.name_input_active = true;
+                } else if name == "REFRESH" {
+                    refresh = true;
+                } else if name == "BACK_ARROW_IMG" {
+                    new_view = Some(View::MainMenu(MainMenuStruct::new(ctx).unwrap()));
+                }
+            }
+        }
+        if refresh {
+            self.error = None;
+            self.refresh();
+        }
+        for i in 0..self.servers.len() {
+            if self.connecting || !ServerBrowserStruct::row_rect(i).contains([mouse_x, mouse_y]) {
+                continue;
+            }
+            if self.name.contents().is_empty() {
+                self.error = Some("Enter your name first".to_string());
+                break;
+            }
+            let (address, info) = &self.servers[i];
+            // the join form asks for the password, name and address are filled in already
+            if info.locked {
+                let mut join = JoinGameStruct::new(ctx).unwrap();
+                join.name = Text::new(self.name.contents());
+                join.ip_address = Text::new(address.to_string());
+                join.password_input_active = true;
+                new_view = Some(View::JoinGame(join));
+                break;
+            }
+            // the state connects and opens the game once the map arrived
+            self.server_address = Some(*address);
+            self.error = None;
+            self.connecting = true;
+            break;
+        }
+        new_view
+    }
+}
@@ -0,0 +1,213 @@
+use std::collections::{BTreeMap, VecDeque};
+
+use crate::player::Direction;
+use crate::protocol::PlayerId;
+
+// worlds kept to diff against, a client that acked nothing this recent gets everything
+pub const SNAPSHOT_HISTORY: usize = 32;
+
+// what every client sees of a player
+#[derive(Debug, Clone, PartialEq)]
+pub struct PlayerState {
+    pub pos: (u16, u16), // grid cell
+    pub dir: Direction,
+    pub score: i32,
+}
+
+pub type World = BTreeMap<PlayerId, PlayerState>;
+
+// the fields of a player that changed since the baseline
+#[derive(Debug, Clone, Default)]
+pub struct PlayerDelta {
+    pub pos: Option<(u16, u16)>,
+    pub dir: Option<Direction>,
+    pub score: Option<i32>,
+}
+
+// The world at one server tick, sent as the difference to the last world the client
+// acked. Baseline 0 means a full snapshot, ticks start at 1.
+#[derive(Debug, Clone)]
+pub struct Snapshot {
+    pub tick: u32,
+    pub baseline: u32,
+    pub last_move: u32, // newest move of the receiving player the world includes
+    pub changed: Vec<(PlayerId, PlayerDelta)>,
+    pub removed: Vec<PlayerId>, // in the baseline but gone now
+}
+
+impl Snapshot {
+    pub fn diff(tick: u32, baseline: Option<(u32, &World)>, world: &World) -> Snapshot {
+        let empty = World::new();
+        let (baseline, old) = baseline.unwrap_or((0, &empty));
+        let changed = world
+            .iter()
+            .filter_map(|(id, state)| {
+                let before = old.get(id);
+                let delta = PlayerDelta {
+                    pos: Some(state.pos).filter(|pos| before.is_none_or(|b| b.pos != *pos)),
+                    dir: Some(state.dir.clone()).filter(|dir| before.is_none_or(|b| b.dir != *dir)),
+                    score: Some(state.score).filter(|score| before.is_none_or(|b| b.score != *score)),
+                };
+                if delta.pos.is_none() && delta.dir.is_none() && delta.score.is_none() {
+                    return None;
+                }
+                Some((*id, delta))
+            })
+            .collect();
+        let removed = old
+            .keys()
+            .filter(|id| !world.contains_key(id))
+            .copied()
+            .collect();
+        Snapshot {
+            tick,
+            baseline,
+            last_move: 0,
+            changed,
+            removed,
+        }
+    }
+    // the world this snapshot describes, None when a new player lacks a field
+    pub fn apply(&self, baseline: &World) -> Option<World> {
+        let mut world = baseline.clone();
+        for id in self.removed.iter() {
+            world.remove(id);
+        }
+        for (id, delta) in self.changed.iter() {
+            let state = match (world.get(id), delta) {
+                (Some(state), _) => PlayerState {
+                    pos: delta.pos.unwrap_or(state.pos),
+                    dir: delta.dir.clone().unwrap_or(state.dir.clone()),
+                    score: delta.score.unwrap_or(state.score),
+                },
+                (None, PlayerDelta { pos: Some(pos), dir: Some(dir), score: Some(score) }) => {
+                    PlayerState {
+                        pos: *pos,
+                        dir: dir.clone(),
+                        score: *score,
+                    }
+                }
+                (None, _) => return None,
+            };
+            world.insert(*id, state);
+        }
+        Some(world)
+    }
+    // the same world as a full snapshot
+    pub fn full(tick: u32, world: &World) -> Snapshot {
+        Snapshot::diff(tick, None, world)
+    }
+}
+
+// the last worlds by tick, on the server to diff against and on the client to apply to
+#[derive(Default)]
+pub struct SnapshotHistory {
+    worlds: VecDeque<(u32, World)>,
+}
+
+impl SnapshotHistory {
+    pub fn push(&mut self, tick: u32, world: World) {
+        if self.worlds.len() == SNAPSHOT_HISTORY {
+            self.worlds.pop_front();
+        }
+        self.worlds.push_back((tick, world));
+    }
+    pub fn get(&self, tick: u32) -> Option<&World> {
+        self.worlds
+            .iter()
+            .find(|(t, _)| *t == tick)
+            .map(|(_, world)| world)
+    }
+    pub fn latest_tick(&self) -> u32 {
+        self.worlds.back().map_or(0, |(tick, _)| *tick)
+    }
+    pub fn clear(&mut self) {
+        self.worlds.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn state(x: u16, dir: Direction, score: i32) -> PlayerState {
+        PlayerState {
+            pos: (x, 1),
+            dir,
+            score,
+        }
+    }
+
+    fn world() -> World {
+        World::from([
+            (1, state(1, Direction::Up, 0)),
+            (2, state(2, Direction::Left, 3)),
+        ])
+    }
+
+    #[test]
+    fn diff_against_the_acked_baseline_has_only_the_changes() {
+        let mut history = SnapshotHistory::default();
+        let baseline = world();
+        history.push(5, baseline.clone());
+        let mut now = baseline.clone();
+        now.get_mut(&1).unwrap().pos = (3, 1);
+        now.get_mut(&2).unwrap().score = 4;
+
+        let snapshot = Snapshot::diff(6, history.get(5).map(|world| (5, world)), &now);
+        assert_eq!(snapshot.baseline, 5);
+        let (moved, scored) = (&snapshot.changed[0], &snapshot.changed[1]);
+        assert_eq!(moved.0, 1);
+        assert_eq!((moved.1.pos, moved.1.dir.clone(), moved.1.score), (Some((3, 1)), None, None));
+        assert_eq!(scored.0, 2);
+        assert_eq!((scored.1.pos, scored.1.dir.clone(), scored.1.score), (None, None, Some(4)));
+        assert_eq!(snapshot.apply(&baseline), Some(now.clone()));
+
+        // nothing changed, nothing sent
+        let snapshot = Snapshot::diff(7, Some((6, &now)), &now);
+        assert!(snapshot.changed.is_empty() && snapshot.removed.is_empty());
+    }
+
+    #[test]
+    fn removed_player_is_gone_after_apply() {
+        let baseline = world();
+        let mut now = baseline.clone();
+        now.remove(&2);
+        let snapshot = Snapshot::diff(6, Some((5, &baseline)), &now);
+        assert_eq!(snapshot.removed, [2]);
+        assert!(snapshot.changed.is_empty());
+        assert_eq!(snapshot.apply(&baseline), Some(now));
+    }
+
+    #[test]
+    fn new_player_needs_every_field() {
+        let partial = Snapshot {
+            tick: 6,
+            baseline: 5,
+            last_move: 0,
+            changed: vec![(3, PlayerDelta {
+                pos: Some((1, 1)),
+                dir: Some(Direction::Up),
+                score: None,
+            })],
+            removed: vec![],
+        };
+        assert_eq!(partial.apply(&world()), None);
+
+        // a full snapshot has them all
+        let full = Snapshot::full(6, &world());
+        assert_eq!(full.baseline, 0);
+        assert_eq!(full.apply(&World::new()), Some(world()));
+    }
+
+    #[test]
+    fn history_forgets_the_oldest_world() {
+        let mut history = SnapshotHistory::default();
+        for tick in 1..=SNAPSHOT_HISTORY as u32 + 1 {
+            history.push(tick, world());
+        }
+        assert!(history.get(1).is_none());
+        assert!(history.get(2).is_some());
+        assert_eq!(history.latest_tick(), SNAPSHOT_HISTORY as u32 + 1);
+    }
+}
@@ -1,15 +1,23 @@
+use std::io;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::mpsc::{channel, Receiver, Sender};
-use std::thread;
-use std::time::Duration;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
 extern crate copypasta;
 
+use crate::admin::AdminConsole;
 use crate::client::Client;
+use crate::game::GameStruct;
 
+use crate::join_game::JoinGameStruct;
 use crate::main_menu::MainMenuStruct;
 pub use crate::map::Map;
-use crate::player::Direction;
 pub use crate::player::Player;
-use crate::server::{Message, Server};
+use crate::protocol::PlayerId;
+use crate::replay::{Origin, Recorder, Replay, SEEK_STEP};
+use crate::replay_browser::ReplayBrowserStruct;
+use crate::server::{HostState, Message, Server};
 use crate::view::{remove_input_text_last_letter, View};
 use ggez::event::{EventHandler, MouseButton};
 use ggez::graphics::{self, Color};
@@ -17,6 +25,17 @@ use ggez::input::keyboard::{self, KeyCode};
 use ggez::{Context, GameError, GameResult};
 use std::sync::{mpsc, Arc};
 use copypasta::{ClipboardContext, ClipboardProvider};
+// gives up on a join when the server did not send the map in time
+const JOIN_TIMEOUT: Duration = Duration::from_secs(10);
+
+// a join in progress, the game view opens once the server sent the map
+struct PendingJoin {
+    name: String,
+    spectator: bool,
+    started: Instant,
+    messages: Vec<Message>, // arrived before the map, handled once the game exists
+}
+
 pub struct State {
     pub view: View,
     pub server_ip: String,
@@ -24,6 +43,10 @@ pub struct State {
     pub client: Option<Arc<Client>>,
     pub map: Option<Map>,
     paste_ctx:ClipboardContext,
+    joining: Option<PendingJoin>,
+    hosting: Option<(Arc<AtomicBool>, JoinHandle<()>)>, // stop flag and thread of our own server
+    console: Option<AdminConsole>, // only the host can run admin commands
+    replay: Option<Replay>,        // set while the game view plays a recorded match
 }
 
 impl State {
@@ -34,92 +57,327 @@ impl State {
             client: None,
             view: View::MainMenu(MainMenuStruct::new(ctx)?),
             map: None,
-            paste_ctx:ClipboardContext::new().unwrap()
+            paste_ctx:ClipboardContext::new().unwrap(),
+            joining: None,
+            hosting: None,
+            console: None,
+            replay: None,
         })
     }
-    fn prepare_player_data_to_send(player_name: &String, player_data: &Player) -> Vec<u8> {
-        serde_json::to_vec(&Message::PlayerMoved(
-            player_name.clone(),
-            (player_data.pos.x, player_data.pos.y),
-            (player_data.dir.vec().x, player_data.dir.vec().y),
-        ))
-        .expect("Cant disserialize.")
+    fn prepare_shoot_data_to_send(player_id: PlayerId, opponent_id: PlayerId) -> Message {
+        Message::PlayerShot((player_id, opponent_id))
+    }
+    // connects to a server hosted somewhere else, used by join game and the server browser
+    fn connect_to_server(
+        &mut self,
+        name: String,
+        password: String,
+        spectator: bool,
+        server_ip: SocketAddr,
+    ) -> io::Result<()> {
+        let client = Arc::new(Client::new(name.clone(), password, spectator, server_ip)?);
+
+        self.client = Some(client.clone());
+        self.server_ip = server_ip.to_string();
+        self.joining = Some(PendingJoin {
+            name,
+            spectator,
+            started: Instant::now(),
+            messages: vec![],
+        });
+        // threads of an earlier connection stop once they notice nobody listens anymore
+        self.channels = mpsc::channel();
+
+        let client_clone = Arc::clone(&client);
+
+        let send_ch = self.channels.0.clone();
+        let send_ch1 = self.channels.0.clone();
+
+        let channels = channel::<bool>();
+        thread::spawn(move || loop {
+            if !client.is_running() {
+                return;
+            }
+            if channels.1.try_recv().is_ok() {
+                println!("Lost connection to server...");
+                let _ = send_ch.send(Message::ConnectionLost);
+                return;
+            };
+
+            client.send_ping_msg();
+            thread::sleep(Duration::from_millis(1000))
+        });
+
+        thread::spawn(move || {
+            client_clone.listen_for_messages(send_ch1);
+            let _ = channels.0.send(true);
+        });
+        Ok(())
+    }
+    // tells the server we are gone and stops the threads of this match,
+    // a hosted server is waited for so its port is free again
+    fn leave_game(&mut self) {
+        if let Some(client) = self.client.take() {
+            client.leave();
+        }
+        self.joining = None;
+        self.console = None;
+        self.replay = None;
+        if let Some((stop, server_thread)) = self.hosting.take() {
+            stop.store(true, Ordering::Relaxed);
+            let _ = server_thread.join();
+        }
+    }
+    // collects messages until the map arrives, then opens the game and replays them
+    fn update_join(&mut self, ctx: &mut Context) -> GameResult {
+        while let Ok(msg) = self.channels.1.try_recv() {
+            match msg {
+                Message::Map(maze) => {
+                    let joining = self.joining.take().unwrap();
+                    let map = Map::new(ctx, maze);
+                    let mut game = GameStruct::new(ctx, joining.name, map, (0., 0.))?;
+                    game.spectator = joining.spectator;
+                    self.view = View::Game(game);
+                    // the server places the player with a Respawn right after the map
+                    for m in joining.messages {
+                        self.handle_message(ctx, m)?;
+                    }
+                    return Ok(());
+                }
+                Message::JoinRejected(reason) => {
+                    self.join_failed(reason);
+                    return Ok(());
+                }
+                Message::ConnectionLost => {
+                    self.join_failed(format!("No answer from {}", self.server_ip));
+                    return Ok(());
+                }
+                Message::ServerClosed => {
+                    self.join_failed("Server closed".to_string());
+                    return Ok(());
+                }
+                m => self.joining.as_mut().unwrap().messages.push(m),
+            }
+        }
+        if self.joining.as_ref().unwrap().started.elapsed() > JOIN_TIMEOUT {
+            self.join_failed(format!("No answer from {}", self.server_ip));
+        }
+        Ok(())
+    }
+    // back to the view the join started from, with the reason
+    fn join_failed(&mut self, reason: String) {
+        self.leave_game();
+        match &mut self.view {
+            View::JoinGame(view_data) => {
+                view_data.connecting = false;
+                view_data.error = Some(reason);
+            }
+            View::ServerBrowser(view_data) => {
+                view_data.connecting = false;
+                view_data.error = Some(reason);
+            }
+            _ => {}
+        }
+    }
+    // the host left and picked us, the match goes on with a server in this process
+    fn take_over(&mut self, state: HostState) -> GameResult {
+        let game = match &self.view {
+            View::Game(game) => game,
+            _ => return Ok(()),
+        };
+        let client = self.client.clone().unwrap();
+        let mut server = match Server::bind_free_port(client.host_ip()?, state.config.clone()) {
+            Ok(server) => server,
+            Err(e) => {
+                println!("Cant take over the match: {}", e);
+                return Ok(());
+            }
+        };
+        server.take_over(state, game.player.id);
+        let server_ip = server.connect_address()?;
+        let maze = game.map.maze.clone();
+        let stop = server.stop_handle();
+        self.console = Some(AdminConsole::new(server.admin_handle()));
+        let server_thread = thread::spawn(move || server.start(maze).unwrap());
+        self.hosting = Some((stop, server_thread));
+
+        // the old host tells everyone else where to go once it knows the port
+        client.take_over(server_ip);
+        self.server_ip = server_ip.to_string();
+        Ok(())
+    }
+    // watches a recorded match like a spectator, the game gets the messages it saw
+    fn start_replay(&mut self, ctx: &mut Context, replay: Replay) -> GameResult {
+        let map = Map::new(ctx, replay.first_map());
+        let mut game = GameStruct::new(ctx, "Replay".to_string(), map, (0., 0.))?;
+        game.spectator = true;
+        // the game is built again when the replay goes back, the view stays on the same player
+        if let View::Game(old) = &self.view {
+            game.watching = old.watching;
+        }
+        self.view = View::Game(game);
+        self.replay = Some(replay);
+        Ok(())
+    }
+    fn update_replay(&mut self, ctx: &mut Context) -> GameResult {
+        let due = self.replay.as_mut().unwrap().advance();
+        for m in due {
+            self.handle_message(ctx, m)?;
+        }
+        Ok(())
+    }
+    // jumps in the replay, going back plays everything up to that point again
+    fn seek_replay(&mut self, ctx: &mut Context, seek: impl FnOnce(&mut Replay) -> bool) -> GameResult {
+        let mut replay = self.replay.take().unwrap();
+        if seek(&mut replay) {
+            self.start_replay(ctx, replay)?;
+        } else {
+            self.replay = Some(replay);
+        }
+        Ok(())
+    }
+    // F5 starts and stops recording the match, the replay starts with what the game knows
+    fn toggle_recording(&mut self) {
+        let (game, client) = match (&mut self.view, &self.client) {
+            (View::Game(game), Some(client)) => (game, client),
+            _ => return,
+        };
+        if client.is_recording() {
+            client.stop_recording();
+            game.chat.system("Recording stopped".to_string());
+            return;
+        }
+        match Recorder::create_new(&game.player.name, Origin::Client) {
+            Ok((recorder, path)) => {
+                let mut players: Vec<(PlayerId, String)> = game
+                    .opponents
+                    .iter()
+                    .map(|opponent| (opponent.id, opponent.name.clone()))
+                    .collect();
+                if !game.spectator {
+                    players.push((game.player.id, game.player.name.clone()));
+                }
+                let seed = vec![
+                    Message::Map(game.map.maze.clone()),
+                    Message::OpponentList(players),
+                ];
+                client.start_recording(recorder, seed);
+                game.chat.system(format!("Recording to {}", path));
+            }
+            Err(e) => game.chat.system(format!("Cant record: {}", e)),
+        }
     }
-    fn prepare_shoot_data_to_send(player_name: String, opponent_name: String) -> Vec<u8> {
-        serde_json::to_vec(&Message::PlayerShot((player_name, opponent_name)))
-            .expect("Cant disserialize.")
+    fn handle_message(&mut self, ctx: &mut Context, msg: Message) -> GameResult {
+        let game = match &mut self.view {
+            View::Game(game) => game,
+            _ => return Ok(()),
+        };
+        match msg {
+            Message::Welcome((id, _)) => {
+                game.resync();
+                // welcomed again after the connection was lost, show the others where we are
+                if game.player.id == id && !game.spectator {
+                    let client = self.client.as_ref().unwrap();
+                    client.send(&game.prepare_move());
+                }
+                game.player.id = id;
+            }
+            Message::JoinRejected(reason) => {
+                let mut join_game = JoinGameStruct::new(ctx)?;
+                join_game.name = graphics::Text::new(game.player.name.clone());
+                join_game.ip_address = graphics::Text::new(self.server_ip.clone());
+                join_game.error = Some(reason);
+                self.leave_game();
+                self.view = View::JoinGame(join_game);
+            }
+            Message::ClientJoined(msg) if msg.0 != game.player.id => {
+                game.chat.system(format!("{} joined", msg.1));
+                game.add_opponents(vec![msg]);
+            }
+            Message::Snapshot(snapshot) => game.apply_snapshot(&snapshot),
+            Message::Respawn((x, y)) => {
+                game.player.pos.x = x as f32;
+                game.player.pos.y = y as f32;
+                // moves sent before the respawn may still arrive after it
+                let client = self.client.as_ref().unwrap();
+                client.send(&game.prepare_move());
+            }
+            Message::OpponentList(list) => game.add_opponents(list),
+            Message::PlayerShot(shot_data) => game.register_shooting(shot_data),
+            Message::Map(data) => {
+                game.map = Map::new(ctx, data);
+            }
+
+            Message::ConnectionLost | Message::ServerClosed => {
+                self.leave_game();
+                self.view = View::MainMenu(MainMenuStruct::new(ctx).unwrap());
+            }
+            Message::PlayerLeft(player)=>{
+                game.chat.system(format!("{} left", game.name_of(player)));
+                game.remove_player(player);
+            }
+            Message::MatchOver(winner) => {
+                game.chat.system(format!("{} won the match", winner));
+                game.reset_scores();
+            }
+            Message::Chat(id, text) => {
+                let name = game.name_of(id);
+                game.chat.say(&name, &text);
+            }
+            Message::ServerNotice(text) => game.chat.system(text),
+            Message::HostMigration(state) => self.take_over(state)?,
+            Message::ServerMoved(address) => {
+                self.server_ip = address.to_string();
+            }
+            _ => {}
+        }
+        Ok(())
     }
 }
 
 impl EventHandler for State {
     fn update(&mut self, ctx: &mut Context) -> GameResult {
-        if let View::Game(game) = &mut self.view {
-            if let Ok(msg) = self.channels.1.try_recv() {
-                match msg {
-                    Message::ClientJoined(msg) => {
-                        if msg.0 != game.player.name {
-                            game.add_opponents(vec![msg.0]);
-                        }
-                    }
-                    Message::PlayerMoved(name, cor, dir) => {
-                        for opponent in game.opponents.iter_mut() {
-                            if opponent.name == name {
-                                opponent.pos.x = cor.0;
-                                opponent.pos.y = cor.1;
-                                opponent.dir = Direction::from_vec(&dir);
-                            }
-                        }
-                    }
-                    Message::OpponentList(list) => game.add_opponents(list),
-                    Message::PlayerShot(shot_data) => {
-                        let got_shot = game.register_shooting(shot_data);
-                        if got_shot {
-                            let client = self.client.as_ref().unwrap();
-                            let m = State::prepare_player_data_to_send(&client.name, &game.player);
-                            client.socket.send_to(&m, self.server_ip.clone())?;
-                        }
-                    }
-                    Message::Map(data) => {
-                        game.map = Map::new(ctx, data);
-                        let real_location = game.map.get_random_location();
-                        game.player.pos.x = real_location.0;
-                        game.player.pos.y = real_location.1;
-                    }
-
-                    Message::ConnectionLost => {
-                        self.view = View::MainMenu(MainMenuStruct::new(ctx).unwrap());
-                        return Ok(());
-                    }
-                    Message::PlayerLeft(player)=>{
-                        game.remove_player(player);
-                    }
-                    _ => {}
-                }
+        if self.replay.is_some() {
+            self.update_replay(ctx)?;
+        } else if self.joining.is_some() {
+            self.update_join(ctx)?;
+        } else if let View::Game(_) = self.view {
+            // snapshots arrive every tick, handle everything that queued up since the last frame
+            while let Ok(msg) = self.channels.1.try_recv() {
+                self.handle_message(ctx, msg)?;
             }
+        }
+        if let Some(console) = &mut self.console {
+            console.poll();
+        }
+        // keys typed into the console or the chat do not move the player, spectators have none
+        let typing = self.console.as_ref().is_some_and(|console| console.open)
+            || matches!(&self.view, View::Game(game) if game.chat.is_typing() || game.spectator);
+        if let (View::Game(game), false) = (&mut self.view, typing) {
             if !ctx.keyboard.is_key_pressed(KeyCode::Space) {
                 game.player.can_shoot = true;
             }
             if ctx.keyboard.is_key_pressed(KeyCode::Up) || ctx.keyboard.is_key_pressed(KeyCode::W) {
                 if game.player.go_forward(&game.map.maze) {
                     let client = self.client.as_ref().unwrap();
-                    let m = State::prepare_player_data_to_send(&client.name, &game.player);
-                    client.socket.send_to(&m, self.server_ip.clone())?;
+                    let m = game.prepare_move();
+                    client.send(&m);
                 }
             }
             if ctx.keyboard.is_key_pressed(KeyCode::Down) || ctx.keyboard.is_key_pressed(KeyCode::S)
             {
                 if game.player.go_backward(&game.map.maze) {
                     let client = self.client.as_ref().unwrap();
-                    let m = State::prepare_player_data_to_send(&client.name, &game.player);
-                    client.socket.send_to(&m, self.server_ip.clone())?;
+                    let m = game.prepare_move();
+                    client.send(&m);
                 }
             }
             if ctx.keyboard.is_key_pressed(KeyCode::Left) || ctx.keyboard.is_key_pressed(KeyCode::A)
             {
                 if game.player.turn_left() {
                     let client = self.client.as_ref().unwrap();
-                    let m = State::prepare_player_data_to_send(&client.name, &game.player);
-                    client.socket.send_to(&m, self.server_ip.clone())?;
+                    let m = game.prepare_move();
+                    client.send(&m);
                 }
             }
             if ctx.keyboard.is_key_pressed(KeyCode::Right)
@@ -127,8 +385,8 @@ impl EventHandler for State {
             {
                 if game.player.turn_right() {
                     let client = self.client.as_ref().unwrap();
-                    let m = State::prepare_player_data_to_send(&client.name, &game.player);
-                    client.socket.send_to(&m, self.server_ip.clone())?;
+                    let m = game.prepare_move();
+                    client.send(&m);
                 }
             }
             if ctx.keyboard.is_key_pressed(KeyCode::Space) {
@@ -138,13 +396,18 @@ impl EventHandler for State {
                         let (shooter, target) = shot.unwrap();
                         let client = self.client.as_ref().unwrap();
                         let m = State::prepare_shoot_data_to_send(shooter, target);
-                        client.socket.send_to(&m, self.server_ip.clone())?;
+                        client.send(&m);
                     }
                     game.player.can_shoot = false;
                 }
             }
+        }
+        if let View::Game(game) = &mut self.view {
             game.update()?;
         }
+        if let View::ServerBrowser(view) = &mut self.view {
+            view.poll_servers();
+        }
         if let View::JoinGame(view) = &mut self.view{
             if ctx.keyboard.is_key_just_pressed(keyboard::KeyCode::V) && ctx.keyboard.is_key_pressed(keyboard::KeyCode::LControl){
                 view.paste_value(self.paste_ctx.get_contents().unwrap());
@@ -155,6 +418,12 @@ impl EventHandler for State {
     fn draw(&mut self, ctx: &mut Context) -> GameResult {
         let mut canvas = graphics::Canvas::from_frame(ctx, Color::WHITE);
         self.view.draw(&mut canvas, ctx)?;
+        if let (View::Game(_), Some(console)) = (&self.view, &self.console) {
+            console.draw(&mut canvas, ctx)?;
+        }
+        if let (View::Game(_), Some(replay)) = (&self.view, &self.replay) {
+            replay.draw(&mut canvas, ctx)?;
+        }
         canvas.finish(ctx)
     }
     fn mouse_button_down_event(
@@ -166,6 +435,8 @@ impl EventHandler for State {
     ) -> Result<(), GameError> {
         if let MouseButton::Left = button {
             let mut new_view = None;
+            let mut connect = None;
+            let mut replay = None;
             match &mut self.view {
                 View::MainMenu(view_data) => {
                     new_view = view_data.check_mouse_click(x, y, ctx);
@@ -173,82 +444,87 @@ impl EventHandler for State {
                 View::JoinGame(view_data) => {
                     view_data.ip_input_active = false;
                     view_data.name_input_active = false;
+                    view_data.password_input_active = false;
                     new_view = view_data.check_mouse_click(x, y, ctx);
+                    if view_data.connecting && self.joining.is_none() {
+                        let password = view_data.password.contents();
+                        let address = view_data.server_address.unwrap();
+                        connect = Some((view_data.name.contents(), password, view_data.spectator, address));
+                    }
                 }
                 View::CreateGame(view_data) => {
                     view_data.name_input_active = false;
+                    view_data.address_input_active = false;
+                    view_data.password_input_active = false;
+                    new_view = view_data.check_mouse_click(x, y, ctx);
+                }
+                View::Game(_) => {
+                    // a click on the progress bar of a replay jumps there
+                    if let Some(to) = self.replay.as_ref().and_then(|r| r.clicked_time(x, y)) {
+                        self.seek_replay(ctx, |replay| replay.seek(to))?;
+                    }
+                }
+                View::Replays(view_data) => {
                     new_view = view_data.check_mouse_click(x, y, ctx);
+                    replay = view_data.replay.take();
                 }
-                View::Game(_) => {}
                 View::CreateMap(view_data) => {
                     view_data.name_input_active = false;
                     view_data.register_click(x, y, ctx);
                     new_view = view_data.check_mouse_click(x, y, ctx);
                 }
+                View::ServerBrowser(view_data) => {
+                    view_data.name_input_active = false;
+                    new_view = view_data.check_mouse_click(x, y, ctx);
+                    if view_data.connecting && self.joining.is_none() {
+                        connect = Some((view_data.name.contents(), String::new(), false, view_data.server_address.unwrap()));
+                    }
+                }
             };
+            // join game and the server browser switch to the game once the map arrived
+            if let Some((name, password, spectator, server_ip)) = connect {
+                if let Err(e) = self.connect_to_server(name, password, spectator, server_ip) {
+                    self.join_failed(format!("Cant connect to {}: {}", server_ip, e));
+                }
+            }
+            if let Some(replay) = replay {
+                self.start_replay(ctx, replay)?;
+            }
 
             if let Some(view) = new_view {
-                if let View::Game(g) = &view {
-                    let previous_view = &self.view;
-                    // if create game was previously -> create server
-                    // if join game was previously -> connect to server
-                    match previous_view {
-                        View::JoinGame(view_data) => {
-                            let name = view_data.name.contents();
-                            let mut server_ip = view_data.ip_address.contents();
-                            if !server_ip.contains(":"){
-                                server_ip = server_ip + ":35353";
-                            }
-                            let client = Arc::new(Client::new(name, server_ip.clone()));
-
-                            self.client = Some(client.clone());
-                            self.server_ip = server_ip.clone().to_string();
-
-                            let client_clone = Arc::clone(&client);
-
-                            let send_ch = self.channels.0.clone();
-                            let send_ch1 = self.channels.0.clone();
-
-                            let channels = channel::<bool>();
-                            thread::spawn(move || loop {
-                                if let Ok(_) = channels.1.try_recv() {
-                                    println!("Lost connection to server...");
-                                    send_ch.send(Message::ConnectionLost).unwrap();
-                                    return;
-                                };
-
-                                client.send_ping_msg();
-                                thread::sleep(Duration::from_millis(1000))
-                            });
+                // left the view while still connecting
+                if self.joining.is_some() {
+                    self.leave_game();
+                }
+                // if create game was previously -> create server
+                if let (View::Game(g), View::CreateGame(view_data)) = (&view, &mut self.view) {
+                    let name = view_data.name.contents();
+                    let password = view_data.password.contents();
+                    self.channels = mpsc::channel();
+                    let send_ch = self.channels.0.clone();
 
-                            thread::spawn(move || {
-                                client_clone.listen_for_messages(send_ch1);
-                                channels.0.send(true).unwrap();
-                            });
+                    let mut server = view_data.server.take().unwrap();
+                    let server_ip = server.connect_address()?;
+                    // create client, without one the server is closed again
+                    let client = match Client::new(name, password, false, server_ip) {
+                        Ok(client) => Arc::new(client),
+                        Err(e) => {
+                            view_data.error = Some(format!("Cant join {}: {}", server_ip, e));
+                            return Ok(());
                         }
-                        View::CreateGame(view_data) => {
-                            let name = view_data.name.contents();
-                            let send_ch = self.channels.0.clone();
-
-                            let mut server = Server::new();
-                            let server_ip =
-                                server.socket.try_clone().unwrap().local_addr().unwrap();
-                            // create client
-                            let client = Arc::new(Client::new(name, server_ip.to_string().clone()));
-                            let client_clone = Arc::clone(&client);
+                    };
+                    let client_clone = Arc::clone(&client);
 
-                            self.client = Some(client.clone());
-                            self.map = Some(g.map.clone());
-                            self.server_ip = server_ip.to_string();
+                    self.client = Some(client.clone());
+                    self.map = Some(g.map.clone());
+                    self.server_ip = server_ip.to_string();
 
-                            let maze = self.map.as_ref().unwrap().maze.clone();
-                            thread::spawn(move || server.start(maze).unwrap());
-                            thread::spawn(move || client_clone.listen_for_messages(send_ch));
-                        }
-                        View::Game(_) => {}
-                        View::MainMenu(_) => {}
-                        View::CreateMap(_) => {}
-                    }
+                    let maze = self.map.as_ref().unwrap().maze.clone();
+                    let stop = server.stop_handle();
+                    self.console = Some(AdminConsole::new(server.admin_handle()));
+                    let server_thread = thread::spawn(move || server.start(maze).unwrap());
+                    self.hosting = Some((stop, server_thread));
+                    thread::spawn(move || client_clone.listen_for_messages(send_ch));
                 }
                 self.view = view;
             }
@@ -295,8 +571,16 @@ impl EventHandler for State {
     }
     fn text_input_event(&mut self, _ctx: &mut Context, character: char) -> Result<(), GameError> {
         match &mut self.view {
-            View::Game(_) => {}
-            View::MainMenu(_) => {}
+            View::Game(game) => {
+                if let Some(console) = self.console.as_mut().filter(|console| console.open) {
+                    if !character.is_control() && console.input.len() <= 60 {
+                        console.input.push(character);
+                    }
+                } else {
+                    game.chat.add(character);
+                }
+            }
+            View::MainMenu(_) | View::Replays(_) => {}
             View::CreateMap(view_data) => {
                 if view_data.name_input_active
                     && character.is_alphanumeric()
@@ -307,10 +591,8 @@ impl EventHandler for State {
             }
             View::JoinGame(view_data) => {
                 if view_data.ip_input_active
-                    && character.is_alphanumeric()
-                    && view_data.ip_address.contents().len() <= 20
-                    || character == '.'
-                    || character == ':'
+                    && (character.is_alphanumeric() || ".:[]".contains(character))
+                    && view_data.ip_address.contents().len() <= 40
                 {
                     view_data.ip_address.add(character);
                 }
@@ -320,6 +602,20 @@ impl EventHandler for State {
                 {
                     view_data.name.add(character);
                 }
+                if view_data.password_input_active
+                    && !character.is_control()
+                    && view_data.password.contents().len() <= 20
+                {
+                    view_data.password.add(character);
+                }
+            }
+            View::ServerBrowser(view_data) => {
+                if view_data.name_input_active
+                    && character.is_alphanumeric()
+                    && view_data.name.contents().len() <= 10
+                {
+                    view_data.name.add(character);
+                }
             }
             View::CreateGame(view_data) => {
                 if view_data.name_input_active
@@ -328,23 +624,105 @@ impl EventHandler for State {
                 {
                     view_data.name.add(character);
                 }
+                if view_data.address_input_active
+                    && (character.is_alphanumeric() || ".:[]".contains(character))
+                    && view_data.address.contents().len() <= 40
+                {
+                    view_data.address.add(character);
+                }
+                if view_data.password_input_active
+                    && !character.is_control()
+                    && view_data.password.contents().len() <= 20
+                {
+                    view_data.password.add(character);
+                }
             }
         }
 
         Ok(())
     }
 
+    fn quit_event(&mut self, _ctx: &mut Context) -> Result<bool, GameError> {
+        self.leave_game();
+        Ok(false)
+    }
+
     fn key_down_event(
         &mut self,
-        _ctx: &mut Context,
+        ctx: &mut Context,
         input: ggez::input::keyboard::KeyInput,
         _repeated: bool,
     ) -> Result<(), GameError> {
         if let Some(keycode) = input.keycode {
+            // the host opens the admin console, see admin.rs
+            if let (View::Game(game), Some(console)) = (&self.view, &mut self.console) {
+                match keycode {
+                    keyboard::KeyCode::F1 if !game.chat.is_typing() => console.open = !console.open,
+                    keyboard::KeyCode::Escape if console.open => {
+                        console.open = false;
+                        return Ok(());
+                    }
+                    keyboard::KeyCode::Return if console.open => console.submit(),
+                    _ => {}
+                }
+            }
+            // space pauses a replay and the arrows skip through it
+            if let (View::Game(_), Some(replay)) = (&self.view, &mut self.replay) {
+                match keycode {
+                    keyboard::KeyCode::Space => replay.toggle_pause(),
+                    keyboard::KeyCode::Left => {
+                        self.seek_replay(ctx, |replay| replay.seek_by(SEEK_STEP, false))?
+                    }
+                    keyboard::KeyCode::Right => {
+                        self.seek_replay(ctx, |replay| replay.seek_by(SEEK_STEP, true))?
+                    }
+                    _ => {}
+                }
+            }
+            if let (keyboard::KeyCode::F5, View::Game(_)) = (keycode, &self.view) {
+                self.toggle_recording();
+            }
+            // enter opens the chat and sends the typed line
+            let console_open = self.console.as_ref().is_some_and(|console| console.open);
+            if let (View::Game(game), false) = (&mut self.view, console_open) {
+                match keycode {
+                    keyboard::KeyCode::Return if game.chat.is_typing() => {
+                        if let Some(text) = game.chat.close() {
+                            let client = self.client.as_ref().unwrap();
+                            client.send(&Message::Chat(game.player.id, text));
+                        }
+                    }
+                    keyboard::KeyCode::Return if self.client.is_some() => game.chat.open(),
+                    keyboard::KeyCode::Escape if game.chat.is_typing() => {
+                        game.chat.close();
+                        return Ok(());
+                    }
+                    keyboard::KeyCode::Tab if game.spectator => game.watch_next(),
+                    keyboard::KeyCode::PageUp => game.chat.scroll_up(),
+                    keyboard::KeyCode::PageDown => game.chat.scroll_down(),
+                    _ => {}
+                }
+            }
+            // leave the match, a replay goes back to the list of replays
+            if let (keyboard::KeyCode::Escape, View::Game(_)) = (keycode, &self.view) {
+                let watched_replay = self.replay.is_some();
+                self.leave_game();
+                self.view = if watched_replay {
+                    View::Replays(ReplayBrowserStruct::new(ctx)?)
+                } else {
+                    View::MainMenu(MainMenuStruct::new(ctx)?)
+                };
+            }
             if let keyboard::KeyCode::Back = keycode {
                 match &mut self.view {
-                    View::Game(_) => {}
-                    View::MainMenu(_) => {}
+                    View::Game(game) => {
+                        if let Some(console) = self.console.as_mut().filter(|console| console.open) {
+                            console.input.pop();
+                        } else {
+                            game.chat.remove_last();
+                        }
+                    }
+                    View::MainMenu(_) | View::Replays(_) => {}
                     View::CreateMap(view_data) => {
                         if view_data.name_input_active {
                             view_data.name =
@@ -360,12 +738,30 @@ impl EventHandler for State {
                             view_data.name =
                                 remove_input_text_last_letter(view_data.name.contents());
                         }
+                        if view_data.password_input_active {
+                            view_data.password =
+                                remove_input_text_last_letter(view_data.password.contents());
+                        }
+                    }
+                    View::ServerBrowser(view_data) => {
+                        if view_data.name_input_active {
+                            view_data.name =
+                                remove_input_text_last_letter(view_data.name.contents());
+                        }
                     }
                     View::CreateGame(view_data) => {
                         if view_data.name_input_active {
                             view_data.name =
                                 remove_input_text_last_letter(view_data.name.contents());
                         }
+                        if view_data.address_input_active {
+                            view_data.address =
+                                remove_input_text_last_letter(view_data.address.contents());
+                        }
+                        if view_data.password_input_active {
+                            view_data.password =
+                                remove_input_text_last_letter(view_data.password.contents());
+                        }
                     }
                 }
             }
@@ -0,0 +1,179 @@
+use std::{
+    collections::HashMap,
+    io,
+    net::{IpAddr, SocketAddr, UdpSocket},
+    sync::{
+        mpsc::{channel, Receiver, RecvTimeoutError, Sender},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+
+// How the server and the clients exchange datagrams. The game runs over UDP, a
+// MemoryNetwork lets a server and its clients talk inside one process without sockets.
+pub trait Transport: Send + Sync {
+    fn send_to(&self, bytes: &[u8], address: SocketAddr) -> io::Result<usize>;
+    // waits at most the read timeout, WouldBlock or TimedOut when nothing arrived
+    fn recv_from(&self, buf: &mut [u8]) -> io::Result<(usize, SocketAddr)>;
+    fn local_addr(&self) -> io::Result<SocketAddr>;
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()>;
+}
+
+impl Transport for UdpSocket {
+    fn send_to(&self, bytes: &[u8], address: SocketAddr) -> io::Result<usize> {
+        UdpSocket::send_to(self, bytes, address)
+    }
+    fn recv_from(&self, buf: &mut [u8]) -> io::Result<(usize, SocketAddr)> {
+        UdpSocket::recv_from(self, buf)
+    }
+    fn local_addr(&self) -> io::Result<SocketAddr> {
+        UdpSocket::local_addr(self)
+    }
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        UdpSocket::set_read_timeout(self, timeout)
+    }
+}
+
+type Datagram = (Vec<u8>, SocketAddr); // bytes and who sent them
+type LossFilter = Box<dyn Fn(&[u8]) -> bool + Send>;
+
+// Addresses of one in-process network, every bound MemoryTransport has an inbox here.
+// Datagrams arrive in the order they were sent and are never lost, unless a test
+// drops them on purpose with set_loss.
+#[derive(Clone, Default)]
+pub struct MemoryNetwork {
+    inner: Arc<Mutex<Network>>,
+}
+
+#[derive(Default)]
+struct Network {
+    inboxes: HashMap<SocketAddr, Sender<Datagram>>,
+    next_port: u16,
+    loss: HashMap<SocketAddr, Loss>,
+}
+
+// datagrams to drop before the next one is delivered, only those `lost` picks count
+struct Loss {
+    count: u32,
+    lost: LossFilter,
+}
+
+impl MemoryNetwork {
+    pub fn new() -> MemoryNetwork {
+        MemoryNetwork::default()
+    }
+    // port 0 picks a free port like a socket would
+    pub fn bind(&self, address: SocketAddr) -> io::Result<MemoryTransport> {
+        let mut network = self.inner.lock().unwrap();
+        let mut address = address;
+        if address.port() == 0 {
+            loop {
+                network.next_port = network.next_port.wrapping_add(1).max(49152);
+                address.set_port(network.next_port);
+                if !network.inboxes.contains_key(&address) {
+                    break;
+                }
+            }
+        }
+        if network.inboxes.contains_key(&address) {
+            return Err(io::Error::new(
+                io::ErrorKind::AddrInUse,
+                format!("{} is already bound", address),
+            ));
+        }
+        let (sender, inbox) = channel();
+        network.inboxes.insert(address, sender);
+        Ok(MemoryTransport {
+            address,
+            network: self.clone(),
+            inbox: Mutex::new(inbox),
+            read_timeout: Mutex::new(None),
+        })
+    }
+    // a transport on the loopback address with a free port, for clients
+    pub fn bind_any(&self) -> io::Result<MemoryTransport> {
+        self.bind(SocketAddr::new(IpAddr::from([127, 0, 0, 1]), 0))
+    }
+    // the next `count` datagrams sent to `address` are lost
+    pub fn set_loss(&self, address: SocketAddr, count: u32) {
+        self.set_loss_when(address, count, |_| true);
+    }
+    // the next `count` datagrams to `address` for which `lost` is true are lost, the
+    // others get through, a test uses it to lose one particular message
+    pub fn set_loss_when(
+        &self,
+        address: SocketAddr,
+        count: u32,
+        lost: impl Fn(&[u8]) -> bool + Send + 'static,
+    ) {
+        let loss = Loss {
+            count,
+            lost: Box::new(lost),
+        };
+        self.inner.lock().unwrap().loss.insert(address, loss);
+    }
+    fn deliver(&self, from: SocketAddr, to: SocketAddr, bytes: &[u8]) {
+        let mut network = self.inner.lock().unwrap();
+        if let Some(loss) = network.loss.get_mut(&to) {
+            if loss.count > 0 && (loss.lost)(bytes) {
+                loss.count -= 1;
+                return;
+            }
+        }
+        // nobody listens there, like UDP the datagram is gone without an error
+        if let Some(inbox) = network.inboxes.get(&to) {
+            let _ = inbox.send((bytes.to_vec(), from));
+        }
+    }
+    fn unbind(&self, address: &SocketAddr) {
+        let mut network = self.inner.lock().unwrap();
+        network.inboxes.remove(address);
+        network.loss.remove(address);
+    }
+}
+
+// One address on a MemoryNetwork, freed when it is dropped
+pub struct MemoryTransport {
+    address: SocketAddr,
+    network: MemoryNetwork,
+    inbox: Mutex<Receiver<Datagram>>,
+    read_timeout: Mutex<Option<Duration>>,
+}
+
+impl Transport for MemoryTransport {
+    fn send_to(&self, bytes: &[u8], address: SocketAddr) -> io::Result<usize> {
+        self.network.deliver(self.address, address, bytes);
+        Ok(bytes.len())
+    }
+    fn recv_from(&self, buf: &mut [u8]) -> io::Result<(usize, SocketAddr)> {
+        let timeout = *self.read_timeout.lock().unwrap();
+        let inbox = self.inbox.lock().unwrap();
+        let (bytes, from) = match timeout {
+            Some(timeout) => match inbox.recv_timeout(timeout) {
+                Ok(datagram) => datagram,
+                Err(RecvTimeoutError::Timeout) => return Err(io::ErrorKind::WouldBlock.into()),
+                Err(RecvTimeoutError::Disconnected) => return Err(io::ErrorKind::NotConnected.into()),
+            },
+            None => inbox
+                .recv()
+                .map_err(|_| io::Error::from(io::ErrorKind::NotConnected))?,
+        };
+        // a datagram longer than the buffer is cut off, like with UDP
+        let amt = bytes.len().min(buf.len());
+        buf[..amt].copy_from_slice(&bytes[..amt]);
+        Ok((amt, from))
+    }
+    fn local_addr(&self) -> io::Result<SocketAddr> {
+        Ok(self.address)
+    }
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        *self.read_timeout.lock().unwrap() = timeout;
+        Ok(())
+    }
+}
+
+impl Drop for MemoryTransport {
+    fn drop(&mut self) {
+        self.network.unbind(&self.address);
+    }
+}
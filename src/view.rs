@@ -5,14 +5,17 @@ use ggez::{
 
 use crate::{
     create_game::CreateGameStruct, game::GameStruct, join_game::JoinGameStruct,
-    main_menu::MainMenuStruct, create_map::CreateMap,
+    main_menu::MainMenuStruct, create_map::CreateMap, server_browser::ServerBrowserStruct,
+    replay_browser::ReplayBrowserStruct,
 };
 pub enum View {
     Game(GameStruct),
     MainMenu(MainMenuStruct),
     JoinGame(JoinGameStruct),
     CreateGame(CreateGameStruct),
-    CreateMap(CreateMap)
+    CreateMap(CreateMap),
+    ServerBrowser(ServerBrowserStruct),
+    Replays(ReplayBrowserStruct),
 }
 impl View {
     pub fn draw(&mut self, canvas: &mut graphics::Canvas, ctx: &mut Context) -> GameResult {
@@ -22,6 +25,8 @@ impl View {
             View::JoinGame(view) => view.draw(canvas, ctx)?,
             View::CreateGame(view) => view.draw(canvas, ctx)?,
             View::CreateMap(view) => view.draw(canvas, ctx)?,
+            View::ServerBrowser(view) => view.draw(canvas, ctx)?,
+            View::Replays(view) => view.draw(canvas, ctx)?,
         };
         Ok(())
     }
@@ -0,0 +1,320 @@
+use std::net::SocketAddr;
+use std::thread;
+use std::time::Duration;
+
+use multiplayer_fps::client::{Client, Connection};
+use multiplayer_fps::player::Direction;
+use multiplayer_fps::protocol::PlayerId;
+use multiplayer_fps::reliability::Packet;
+use multiplayer_fps::server::{Message, Server, ServerConfig};
+use multiplayer_fps::transport::{MemoryNetwork, Transport};
+
+// the server and every client take one step per round, a test that waits longer
+// than this for a message failed
+const MAX_ROUNDS: u32 = 1000;
+// the clients ping this often, the server drops players it does not hear from
+const PING_ROUNDS: u32 = 20;
+
+// two free cells next to each other, whoever looks along the corridor sees the other
+fn corridor() -> Vec<Vec<i32>> {
+    vec![vec![1, 1, 1, 1], vec![1, 0, 0, 1], vec![1, 1, 1, 1]]
+}
+
+fn other_cell(cell: (u16, u16)) -> (u16, u16) {
+    if cell.0 == 1 {
+        (2, 1)
+    } else {
+        (1, 1)
+    }
+}
+
+// picks the datagrams that carry a message `pick` is true for
+fn carrying(pick: fn(&Message) -> bool) -> impl Fn(&[u8]) -> bool + Send {
+    move |bytes| match Packet::from_bytes(bytes) {
+        Ok(Packet::Reliable(_, m)) | Ok(Packet::Unreliable(m)) => pick(&m),
+        _ => false,
+    }
+}
+
+struct TestClient {
+    client: Client,
+    connection: Connection,
+    inbox: Vec<Message>,
+    id: PlayerId,
+    cell: (u16, u16),
+    moves: u32,
+}
+
+// a server and its clients driven from one thread, every run goes the same way
+struct Lan {
+    network: MemoryNetwork,
+    server: Option<Server>, // None while the match is handed over
+    address: SocketAddr,
+    clients: Vec<TestClient>,
+    rounds: u32,
+}
+
+impl Lan {
+    // the first player owns a server that is not dedicated
+    fn new(dedicated: bool) -> Lan {
+        let network = MemoryNetwork::new();
+        let transport = network.bind("10.0.0.1:35353".parse().unwrap()).unwrap();
+        let address = transport.local_addr().unwrap();
+        let config = ServerConfig {
+            dedicated,
+            ..ServerConfig::default()
+        };
+        let mut server = Server::with_transport(Box::new(transport), config).unwrap();
+        server.open(corridor());
+        Lan {
+            network,
+            server: Some(server),
+            address,
+            clients: Vec::new(),
+            rounds: 0,
+        }
+    }
+    fn join(&mut self, name: &str) -> usize {
+        let transport = self.network.bind_any().unwrap();
+        let client = Client::with_transport(
+            name.to_string(),
+            String::new(),
+            false,
+            self.address,
+            Box::new(transport),
+        );
+        // only the server waits for datagrams, the clients just look
+        client
+            .socket
+            .set_read_timeout(Some(Duration::from_millis(1)))
+            .unwrap();
+        self.clients.push(TestClient {
+            client,
+            connection: Connection::new(),
+            inbox: Vec::new(),
+            id: 0,
+            cell: (0, 0),
+            moves: 0,
+        });
+        let index = self.clients.len() - 1;
+
+        let (mut id, mut cell) = (None, None);
+        self.wait_for(index, |m| {
+            match m {
+                Message::Welcome((welcome_id, _)) => id = Some(*welcome_id),
+                Message::Respawn(respawn_cell) => cell = Some(*respawn_cell),
+                Message::JoinRejected(reason) => panic!("{} was rejected: {}", name, reason),
+                _ => {}
+            }
+            (id.is_some() && cell.is_some()).then_some(())
+        });
+        let joined = &mut self.clients[index];
+        joined.id = id.unwrap();
+        joined.cell = cell.unwrap();
+        index
+    }
+    fn round(&mut self) {
+        if let Some(server) = &mut self.server {
+            server.step().unwrap();
+        }
+        for c in &mut self.clients {
+            if self.rounds % PING_ROUNDS == 0 && c.client.is_running() {
+                c.client.send_ping_msg();
+            }
+            if let Some(messages) = c.client.poll(&mut c.connection) {
+                c.inbox.extend(messages);
+            }
+        }
+        self.rounds += 1;
+    }
+    // runs rounds until `check` picks a message the client got, the messages before
+    // it are dropped
+    fn wait_for<T>(&mut self, client: usize, mut check: impl FnMut(&Message) -> Option<T>) -> T {
+        for _ in 0..MAX_ROUNDS {
+            let inbox = &mut self.clients[client].inbox;
+            let found = inbox
+                .iter()
+                .enumerate()
+                .find_map(|(i, m)| check(m).map(|found| (i, found)));
+            if let Some((i, found)) = found {
+                inbox.drain(..=i);
+                return found;
+            }
+            self.round();
+        }
+        panic!("client {} waited {} rounds in vain", client, MAX_ROUNDS);
+    }
+    // `client` walks or turns, the move shows in the next snapshot `watcher` gets
+    fn move_to(&mut self, client: usize, cell: (u16, u16), dir: Direction, watcher: usize) {
+        let mover = &mut self.clients[client];
+        mover.moves += 1;
+        let id = mover.id;
+        mover
+            .client
+            .send(&Message::PlayerMoved(id, cell, dir.clone(), mover.moves));
+        self.wait_for(watcher, |m| match m {
+            Message::Snapshot(snapshot) => snapshot
+                .changed
+                .iter()
+                .find(|(changed, _)| *changed == id)
+                .filter(|(_, state)| state.pos == Some(cell) && state.dir == Some(dir.clone()))
+                .map(|_| ()),
+            _ => None,
+        });
+        self.clients[client].cell = cell;
+    }
+}
+
+const ALICE: usize = 0;
+const BOB: usize = 1;
+const CAROL: usize = 2;
+
+// two players join, walk apart, one shoots the other and both leave, when `lossy`
+// the network loses the first join, the shot on every leg and the first farewell
+fn play_match(lossy: bool) {
+    let mut lan = Lan::new(true);
+    if lossy {
+        let server = lan.address;
+        lan.network
+            .set_loss_when(server, 1, carrying(|m| matches!(m, Message::Join(..))));
+    }
+    lan.join("alice");
+    lan.join("bob");
+    let bob_id = lan.clients[BOB].id;
+    lan.wait_for(ALICE, |m| match m {
+        Message::ClientJoined((id, name)) if *id == bob_id => Some(name.clone()),
+        _ => None,
+    });
+
+    // bob always walks, alice steps aside when he ends up on her cell
+    let cell = other_cell(lan.clients[BOB].cell);
+    lan.move_to(BOB, cell, Direction::Left, ALICE);
+    if lan.clients[BOB].cell == lan.clients[ALICE].cell {
+        let cell = other_cell(lan.clients[ALICE].cell);
+        lan.move_to(ALICE, cell, Direction::Left, BOB);
+    }
+    let (alice_cell, bob_cell) = (lan.clients[ALICE].cell, lan.clients[BOB].cell);
+    let towards_bob = if bob_cell.0 > alice_cell.0 {
+        Direction::Right
+    } else {
+        Direction::Left
+    };
+    lan.move_to(ALICE, alice_cell, towards_bob, BOB);
+
+    // the shot and its broadcast are reliable, they are resent until they get through
+    if lossy {
+        let shot = || carrying(|m| matches!(m, Message::PlayerShot(_)));
+        let peers = [
+            lan.address,
+            lan.clients[ALICE].client.socket.local_addr().unwrap(),
+            lan.clients[BOB].client.socket.local_addr().unwrap(),
+        ];
+        for address in peers {
+            lan.network.set_loss_when(address, 1, shot());
+        }
+    }
+    let alice_id = lan.clients[ALICE].id;
+    lan.clients[ALICE]
+        .client
+        .send(&Message::PlayerShot((alice_id, bob_id)));
+    for watcher in [ALICE, BOB] {
+        let hit = lan.wait_for(watcher, |m| match m {
+            Message::PlayerShot(hit) => Some(*hit),
+            Message::ShotRejected(_) => panic!("the server rejected a legal shot"),
+            _ => None,
+        });
+        assert_eq!(hit, (alice_id, bob_id));
+    }
+
+    // a move bob made before his Respawn arrived is dropped without counting against
+    // him, once he moved from the new cell it counts again
+    let cell = lan.wait_for(BOB, |m| match m {
+        Message::Respawn(cell) => Some(*cell),
+        _ => None,
+    });
+    let violations = |lan: &Lan| {
+        let server = lan.server.as_ref().unwrap();
+        server.move_violations.get(&bob_id).copied().unwrap_or(0)
+    };
+    for (dir, counted) in [(Direction::Down, 0), (Direction::Up, 1)] {
+        let bob = &mut lan.clients[BOB];
+        bob.moves += 1;
+        bob.client
+            .send(&Message::PlayerMoved(bob_id, (0, 0), dir.clone(), bob.moves));
+        lan.move_to(BOB, cell, dir, ALICE);
+        assert_eq!(violations(&lan), counted);
+    }
+
+    // Leave is repeated instead of resent
+    if lossy {
+        let server = lan.address;
+        lan.network
+            .set_loss_when(server, 1, carrying(|m| matches!(m, Message::Leave)));
+    }
+    lan.clients[BOB].client.leave();
+    lan.wait_for(ALICE, |m| match m {
+        Message::PlayerLeft(id) if *id == bob_id => Some(()),
+        _ => None,
+    });
+    lan.clients[ALICE].client.leave();
+}
+
+#[test]
+fn join_move_shoot_leave() {
+    play_match(false);
+}
+
+#[test]
+fn lost_datagrams_are_resent() {
+    play_match(true);
+}
+
+// the host quits, the next player takes the match over and the last one follows
+#[test]
+fn host_migration() {
+    let mut lan = Lan::new(false);
+    lan.join("alice");
+    lan.join("bob");
+    lan.join("carol");
+    let (bob_id, carol_id) = (lan.clients[BOB].id, lan.clients[CAROL].id);
+
+    // the old server waits for the successor while handing over, it gets a thread
+    lan.clients[ALICE].client.leave();
+    let mut old = lan.server.take().unwrap();
+    let old_thread = thread::spawn(move || old.shutdown());
+    let state = lan.wait_for(BOB, |m| match m {
+        Message::HostMigration(state) => Some(state.clone()),
+        _ => None,
+    });
+
+    // what the game does in State::take_over
+    let bob = &lan.clients[BOB].client;
+    let ip = bob.host_ip().unwrap();
+    let transport = lan.network.bind(SocketAddr::new(ip, 0)).unwrap();
+    let mut server = Server::with_transport(Box::new(transport), state.config.clone()).unwrap();
+    server.take_over(state, bob_id);
+    server.open(corridor());
+    let address = server.connect_address().unwrap();
+    bob.take_over(address);
+    lan.server = Some(server);
+    lan.address = address;
+
+    for resumed in [BOB, CAROL] {
+        let id = lan.wait_for(resumed, |m| match m {
+            Message::Welcome((id, _)) => Some(*id),
+            Message::ServerClosed => panic!("the match was not handed over"),
+            _ => None,
+        });
+        assert_eq!(id, lan.clients[resumed].id);
+    }
+    old_thread.join().unwrap();
+
+    // the new server runs the match, carol sees where bob goes
+    let cell = other_cell(lan.clients[BOB].cell);
+    lan.move_to(BOB, cell, Direction::Left, CAROL);
+    lan.clients[CAROL].client.leave();
+    lan.wait_for(BOB, |m| match m {
+        Message::PlayerLeft(id) if *id == carol_id => Some(()),
+        _ => None,
+    });
+}